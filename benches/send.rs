@@ -0,0 +1,87 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use tokio_fluent::record::Map;
+use tokio_fluent::testing::MockFluentServer;
+use tokio_fluent::{AsyncFluentClient, Client, Config, Error, FluentClient};
+
+const RECORDS: usize = 1000;
+
+fn record(i: usize) -> Map {
+    let mut map = Map::new();
+    map.insert("i".to_string(), i.into());
+    map.insert("service".to_string(), "bench".into());
+    map.insert(
+        "message".to_string(),
+        "the quick brown fox jumps over the lazy dog".into(),
+    );
+    map
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let map = record(0);
+    c.bench_function("encode", |b| b.iter(|| map.encode().unwrap()));
+}
+
+fn bench_send(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let server = runtime.block_on(MockFluentServer::bind_tcp()).unwrap();
+    let config = Config {
+        max_in_flight: 64,
+        ..Default::default()
+    };
+    let client = runtime
+        .block_on(Client::new_tcp(server.local_addr().unwrap(), &config))
+        .unwrap();
+
+    let mut group = c.benchmark_group("send");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    group.bench_function("map", |b| {
+        b.iter_batched(
+            || (0..RECORDS).map(record).collect::<Vec<_>>(),
+            |records| {
+                runtime.block_on(async {
+                    for map in records {
+                        AsyncFluentClient::send(&client, "bench", map)
+                            .await
+                            .unwrap();
+                    }
+                    client.flush().await.unwrap();
+                });
+                server.clear();
+            },
+            BatchSize::LargeInput,
+        );
+    });
+    group.bench_function("encoded", |b| {
+        b.iter_batched(
+            || {
+                (0..RECORDS)
+                    .map(|i| record(i).encode().unwrap())
+                    .collect::<Vec<_>>()
+            },
+            |records| {
+                runtime.block_on(async {
+                    for encoded in records {
+                        // send_encoded does not wait for the queue, so wait here when it is full
+                        loop {
+                            match client.send_encoded("bench", encoded.clone()) {
+                                Ok(()) => break,
+                                Err(Error::QueueFull) => tokio::task::yield_now().await,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                    client.flush().await.unwrap();
+                });
+                server.clear();
+            },
+            BatchSize::LargeInput,
+        );
+    });
+    group.finish();
+
+    FluentClient::stop(client).unwrap();
+}
+
+criterion_group!(benches, bench_encode, bench_send);
+criterion_main!(benches);
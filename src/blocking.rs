@@ -0,0 +1,261 @@
+//! A blocking client for synchronous code.
+//!
+//! [`BlockingClient`] owns a tokio runtime with one background thread, which runs the workers
+//! of an ordinary [`Client`]. Its methods block the calling thread, so it can be used from
+//! synchronous programs and FFI callbacks without a runtime of their own.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use tokio_fluent::Config;
+//! use tokio_fluent::blocking::BlockingClient;
+//! use tokio_fluent::record::Map;
+//!
+//! fn main() {
+//!     let client =
+//!         BlockingClient::new_tcp("127.0.0.1:24224".parse().unwrap(), &Config::default())
+//!             .unwrap();
+//!
+//!     let mut map = Map::new();
+//!     map.insert("age".to_string(), 22.into());
+//!     client.send("fluent.test", map).unwrap();
+//!     client.close().unwrap();
+//! }
+//! ```
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::builder::ClientBuilder;
+use crate::client::{AsyncFluentClient, Client, Config};
+use crate::error::Error;
+use crate::record::Map;
+use crate::stats::Stats;
+
+/// A fluentd client whose methods block the calling thread.
+///
+/// The methods must not be called from async code, because blocking a thread of
+/// a tokio runtime panics. Use [`Client`] there instead.
+#[derive(Debug)]
+pub struct BlockingClient {
+    client: Client,
+    runtime: Option<Runtime>,
+}
+
+impl BlockingClient {
+    /// Connect to the fluentd server using TCP and start the workers on a background thread.
+    pub fn new_tcp(addr: SocketAddr, config: &Config) -> Result<BlockingClient, Error> {
+        Self::new_with(|| Client::new_tcp(addr, config))
+    }
+
+    /// Connect to the fluentd server using unix domain socket and start the workers
+    /// on a background thread.
+    pub fn new_unix<P: AsRef<Path> + Send>(
+        path: P,
+        config: &Config,
+    ) -> Result<BlockingClient, Error> {
+        Self::new_with(|| Client::new_unix(path, config))
+    }
+
+    /// Connect to the fluentd server at `url`, such as `tcp://127.0.0.1:24224`,
+    /// and start the workers on a background thread. See [`Client::connect`].
+    pub fn connect(url: &str, config: &Config) -> Result<BlockingClient, Error> {
+        Self::new_with(|| Client::connect(url, config))
+    }
+
+    /// Build a client with `builder` and start its workers on a background thread.
+    pub fn from_builder(builder: ClientBuilder) -> Result<BlockingClient, Error> {
+        Self::new_with(|| builder.build())
+    }
+
+    fn new_with<F, Fut>(connect: F) -> Result<BlockingClient, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<Client, Error>>,
+    {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tokio-fluent")
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(connect())?;
+        Ok(Self {
+            client,
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Send a fluent record, blocking while the queue of the worker is full.
+    pub fn send(&self, tag: &str, record: Map) -> Result<(), Error> {
+        self.block_on(AsyncFluentClient::send(&self.client, tag, record))
+    }
+
+    /// Block until all the records sent before are written to the fluentd server or given up.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.block_on(self.client.flush())
+    }
+
+    /// Flush the records sent before, stop the workers and the background thread.
+    ///
+    /// It blocks as long as the retry policy keeps retrying, which is forever with
+    /// a policy such as [`ExponentialBackoff::forever`](crate::retry::ExponentialBackoff::forever)
+    /// while the server is down. Use [`BlockingClient::close_timeout`] to bound it.
+    pub fn close(mut self) -> Result<(), Error> {
+        let result = self.block_on(self.client.shutdown());
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+        result
+    }
+
+    /// Like [`BlockingClient::close`], but give up after `timeout` with [`Error::CloseTimeout`],
+    /// dropping the records which are not written yet.
+    pub fn close_timeout(mut self, timeout: Duration) -> Result<(), Error> {
+        let result = self.shutdown_timeout(timeout);
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+        result
+    }
+
+    /// Return a snapshot of the statistics of the client and its workers.
+    pub fn stats(&self) -> Stats {
+        self.client.stats()
+    }
+
+    fn shutdown_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.block_on(async { tokio::time::timeout(timeout, self.client.shutdown()).await })
+            .unwrap_or(Err(Error::CloseTimeout(timeout)))
+    }
+
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        match &self.runtime {
+            Some(runtime) => runtime.block_on(future),
+            None => unreachable!("the runtime is only taken when the client is closed"),
+        }
+    }
+}
+
+/// How long dropping a client waits for the records sent before to be flushed.
+const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The records sent before are flushed when the client is dropped outside of a runtime,
+/// waiting at most 5 seconds. Call [`BlockingClient::close_timeout`] to choose the timeout
+/// and see whether the records were flushed.
+impl Drop for BlockingClient {
+    fn drop(&mut self) {
+        if self.runtime.is_none() {
+            return;
+        }
+        if Handle::try_current().is_err() {
+            let _ = self.shutdown_timeout(DROP_TIMEOUT);
+        }
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Fault, MockFluentServer};
+
+    #[test]
+    fn test_blocking_client() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime
+            .block_on(MockFluentServer::bind_tcp())
+            .unwrap();
+
+        let client =
+            BlockingClient::new_tcp(server.local_addr().unwrap(), &Config::default()).unwrap();
+        for i in 0..3 {
+            let mut map = Map::new();
+            map.insert("i".to_string(), i.into());
+            client.send("test", map).unwrap();
+        }
+        client.flush().unwrap();
+        assert_eq!(server.received().len(), 3);
+        assert_eq!(client.stats().acked, 3);
+
+        client.send("test", Map::new()).unwrap();
+        client.close().unwrap();
+        assert_eq!(server.received().len(), 4);
+    }
+
+    #[test]
+    fn test_blocking_client_drop_flushes() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime
+            .block_on(MockFluentServer::bind_tcp())
+            .unwrap();
+
+        let client =
+            BlockingClient::new_tcp(server.local_addr().unwrap(), &Config::default()).unwrap();
+        client.send("test", Map::new()).unwrap();
+        drop(client);
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[test]
+    fn test_blocking_client_connect() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime
+            .block_on(MockFluentServer::bind_tcp())
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client =
+            BlockingClient::connect(&format!("tcp://{}", addr), &Config::default()).unwrap();
+        client.send("test", Map::new()).unwrap();
+        client.close().unwrap();
+
+        let client =
+            BlockingClient::from_builder(Client::builder().tcp(addr).tag_prefix("app")).unwrap();
+        client.send("test", Map::new()).unwrap();
+        client.close().unwrap();
+
+        let tags: Vec<_> = server.received().into_iter().map(|r| r.0).collect();
+        assert_eq!(tags, ["test", "app.test"]);
+        assert!(matches!(
+            BlockingClient::from_builder(ClientBuilder::new()),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_blocking_client_close_timeout() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime
+            .block_on(MockFluentServer::bind_tcp())
+            .unwrap();
+        server.inject(Fault::Delay(Duration::from_secs(60)));
+
+        let client =
+            BlockingClient::new_tcp(server.local_addr().unwrap(), &Config::default()).unwrap();
+        client.send("test", Map::new()).unwrap();
+        let timeout = Duration::from_millis(100);
+        assert!(matches!(
+            client.close_timeout(timeout),
+            Err(Error::CloseTimeout(t)) if t == timeout
+        ));
+    }
+
+    #[test]
+    fn test_blocking_client_connect_error() {
+        let server_runtime = Runtime::new().unwrap();
+        let addr = server_runtime.block_on(async {
+            let server = MockFluentServer::bind_tcp().await.unwrap();
+            server.local_addr().unwrap()
+        });
+
+        assert!(matches!(
+            BlockingClient::new_tcp(addr, &Config::default()),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,309 @@
+//! A builder of clients.
+//!
+//! [`ClientBuilder`] chooses the transport and sets the options of [`Config`] one by one.
+//! [`ClientBuilder::build`] checks the config before any connection is attempted,
+//! so a mistake is reported as [`Error::InvalidConfig`] instead of a failure to connect.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use tokio_fluent::{Client, FluentClient};
+//! use tokio_fluent::record::Map;
+//! use tokio_fluent::retry::ExponentialBackoff;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = Client::builder()
+//!         .tcp("127.0.0.1:24224".parse().unwrap())
+//!         .timeout(Duration::from_secs(5))
+//!         .retry_policy(ExponentialBackoff::new(
+//!             Duration::from_millis(100),
+//!             Duration::from_secs(10),
+//!         ))
+//!         .tag_prefix("myapp")
+//!         .workers(2)
+//!         .build()
+//!         .await
+//!         .unwrap();
+//!
+//!     let mut map = Map::new();
+//!     map.insert("age".to_string(), 22.into());
+//!     client.send("fluent.test", map).unwrap();
+//! }
+//! ```
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::client::{Client, Config, ErrorHandler, TcpOptions};
+use crate::endpoint::Endpoint;
+use crate::error::Error;
+use crate::fields::DefaultFields;
+use crate::filter::FilterChain;
+use crate::retry::RetryPolicy;
+use crate::sampling::Sampling;
+use crate::tag::TagPolicy;
+
+#[derive(Debug, Clone, Default)]
+/// A builder of a [`Client`], created by [`Client::builder`].
+///
+/// The options which are not set keep the defaults of [`Config`].
+pub struct ClientBuilder {
+    endpoint: Option<Endpoint>,
+    config: Config,
+}
+
+impl ClientBuilder {
+    /// Create a builder with the default config and no transport.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a builder starting from `config`.
+    /// The endpoint of the config is used unless a transport is chosen.
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            endpoint: None,
+            config,
+        }
+    }
+
+    /// Connect to the fluentd server at `addr` using TCP.
+    pub fn tcp(mut self, addr: SocketAddr) -> Self {
+        self.endpoint = Some(addr.into());
+        self
+    }
+
+    /// Connect to the fluentd server at `path` using unix domain socket.
+    pub fn unix(mut self, path: impl Into<PathBuf>) -> Self {
+        self.endpoint = Some(Endpoint::Unix(path.into()));
+        self
+    }
+
+    /// Connect to the fluentd server at `endpoint`, e.g. one parsed from a URL
+    /// such as `tcp://host:24224`.
+    pub fn endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Set the timeout to connect to the fluentd server. See [`Config::timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Set the retry policy. See [`Config::retry_policy`].
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, retry_policy: P) -> Self {
+        self.config.retry_policy = Arc::new(retry_policy);
+        self
+    }
+
+    /// Set the maximum lifetime of a connection. See [`Config::max_connection_lifetime`].
+    pub fn max_connection_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.max_connection_lifetime = lifetime;
+        self
+    }
+
+    /// Set the prefix joined to every tag. See [`Config::tag_prefix`].
+    pub fn tag_prefix(mut self, tag_prefix: impl Into<String>) -> Self {
+        self.config.tag_prefix = Some(tag_prefix.into());
+        self
+    }
+
+    /// Set what to do with invalid tags. See [`Config::tag_policy`].
+    pub fn tag_policy(mut self, tag_policy: TagPolicy) -> Self {
+        self.config.tag_policy = tag_policy;
+        self
+    }
+
+    /// Set the fields merged into every record. See [`Config::default_fields`].
+    pub fn default_fields(mut self, default_fields: DefaultFields) -> Self {
+        self.config.default_fields = default_fields;
+        self
+    }
+
+    /// Set the sampling rules. See [`Config::sampling`].
+    pub fn sampling(mut self, sampling: Sampling) -> Self {
+        self.config.sampling = sampling;
+        self
+    }
+
+    /// Set the filters applied to every record. See [`Config::filters`].
+    pub fn filters(mut self, filters: FilterChain) -> Self {
+        self.config.filters = filters;
+        self
+    }
+
+    /// Set the number of workers. See [`Config::workers`].
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Set the maximum number of records waiting for their acks. See [`Config::max_in_flight`].
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.config.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Set the timeout of writing a record. See [`Config::write_timeout`].
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Set the timeout of waiting for an ack. See [`Config::ack_timeout`].
+    pub fn ack_timeout(mut self, timeout: Duration) -> Self {
+        self.config.ack_timeout = timeout;
+        self
+    }
+
+    /// Set the interval of health checks. See [`Config::health_check_interval`].
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.config.health_check_interval = interval;
+        self
+    }
+
+    /// Set the options of TCP sockets. See [`Config::tcp`].
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.config.tcp = options;
+        self
+    }
+
+    /// Set the handler of the records which failed to be sent. See [`Config::error_handler`].
+    pub fn error_handler<H: ErrorHandler + 'static>(mut self, error_handler: H) -> Self {
+        self.config.error_handler = Some(Arc::new(error_handler));
+        self
+    }
+
+    /// The config built so far.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The endpoint chosen by the builder, or else the endpoint of the config,
+    /// after checking it and the config.
+    fn checked_endpoint(&self) -> Result<&Endpoint, Error> {
+        let endpoint = self
+            .endpoint
+            .as_ref()
+            .or(self.config.endpoint.as_ref())
+            .ok_or_else(|| {
+                Error::InvalidConfig(
+                    "no transport is chosen; call tcp(), unix() or endpoint(), or set the endpoint of the config".to_string(),
+                )
+            })?;
+        self.config.validate()?;
+        Ok(endpoint)
+    }
+
+    /// Check the transport and the config without connecting.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.checked_endpoint().map(|_| ())
+    }
+
+    /// Check the config, connect to the fluentd server and create the workers with tokio::spawn.
+    pub async fn build(self) -> Result<Client, Error> {
+        let endpoint = self.checked_endpoint()?;
+        Client::open(endpoint, &self.config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{AsyncFluentClient, TcpKeepaliveOptions};
+    use crate::record::Map;
+    use crate::retry::ExponentialBackoff;
+    use crate::sampling::RateLimit;
+    use crate::testing::MockFluentServer;
+
+    async fn unused_addr() -> SocketAddr {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        server.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build() {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::builder()
+            .tcp(server.local_addr().unwrap())
+            .timeout(Duration::from_secs(1))
+            .tag_prefix("app")
+            .workers(2)
+            .build()
+            .await
+            .unwrap();
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        assert_eq!(server.received()[0].0, "app.test");
+    }
+
+    #[tokio::test]
+    async fn test_build_rejects_invalid_config_before_connecting() {
+        // Nothing listens on the address, so connecting would fail with Error::Io.
+        let addr = unused_addr().await;
+        let cases = [
+            ClientBuilder::new(),
+            Client::builder().tcp(addr).timeout(Duration::ZERO),
+            Client::builder().tcp(addr).workers(0),
+            Client::builder().tcp(addr).max_in_flight(0),
+            Client::builder()
+                .tcp(addr)
+                .retry_policy(ExponentialBackoff::new(
+                    Duration::from_secs(2),
+                    Duration::from_secs(1),
+                )),
+            Client::builder().tcp(addr).tcp_options(TcpOptions {
+                keepalive: Some(TcpKeepaliveOptions::new(Duration::ZERO)),
+                ..Default::default()
+            }),
+            Client::builder()
+                .tcp(addr)
+                .sampling(Sampling::new().rule("**", RateLimit::new(0.0, 1))),
+        ];
+        for builder in cases {
+            let result = builder.clone().build().await;
+            assert!(
+                matches!(result, Err(Error::InvalidConfig(_))),
+                "{:?}: {:?}",
+                builder,
+                result
+            );
+        }
+
+        let result = Client::builder()
+            .unix("/nonexistent/fluent.sock")
+            .tag_prefix("my app")
+            .tag_policy(TagPolicy::Validate)
+            .build()
+            .await;
+        assert!(matches!(result, Err(Error::InvalidTag(_))));
+    }
+
+    #[test]
+    fn test_with_config() {
+        let builder = ClientBuilder::with_config(Config {
+            workers: 4,
+            ..Default::default()
+        })
+        .max_in_flight(8);
+        assert_eq!(builder.config().workers, 4);
+        assert_eq!(builder.config().max_in_flight, 8);
+        assert!(builder.validate().is_err());
+        assert!(builder.unix("/tmp/fluent.sock").validate().is_ok());
+
+        let builder = ClientBuilder::with_config(Config {
+            endpoint: Some("unix:///tmp/fluent.sock".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(builder.validate().is_ok());
+    }
+}
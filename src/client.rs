@@ -21,158 +21,544 @@
 //! }
 //! ```
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Result as AnyhowResult;
-use base64::{engine::general_purpose, Engine};
-use tokio::sync::broadcast::{channel, Sender};
-use uuid::Uuid;
-
-use crate::record::Map;
-use crate::worker::{
-    Message, Options, Record, RetryConfig, TCPConnectionConfig, UnixSocketConfig, Worker,
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::lookup_host,
+    sync::{
+        mpsc::{channel, error::TrySendError, Sender},
+        oneshot,
+    },
+    time::timeout,
 };
 
-#[derive(Debug, Clone)]
-pub struct SendError {
-    source: String,
-}
+use crate::builder::ClientBuilder;
+use crate::config;
+use crate::endpoint::Endpoint;
+use crate::error::Error;
+use crate::fields::DefaultFields;
+use crate::filter::{Decision, FilterChain};
+use crate::record::{EncodedMap, Map};
+use crate::retry::{ExponentialBackoff, RetryPolicy};
+use crate::sampling::Sampling;
+use crate::stats::{Metrics, Stats};
+use crate::tag::{self, TagPolicy};
+use crate::worker::{Connectable, Message, TCPConnectionConfig, UnixSocketConfig, Worker};
+pub use crate::worker::{ErrorHandler, Record};
 
-impl std::error::Error for SendError {}
+#[deprecated(note = "use tokio_fluent::Error instead")]
+/// The former error type of sending operations, now an alias of [`Error`].
+/// Like the former type, it implements `Clone` and `Display`.
+pub type SendError = Error;
 
-impl std::fmt::Display for SendError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.source)
-    }
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 /// Config for a client.
+///
+/// It can be deserialized with serde, e.g. from a TOML file, or read from environment variables
+/// by [`Config::from_env`]. Durations are written in the humantime format, such as `"3s"`.
+/// `retry_policy` is read as the parameters of [`ExponentialBackoff`],
+/// and `default_fields` as a table of string values.
+/// `sampling`, `filters` and `error_handler` can only be set in code.
 pub struct Config {
+    /// The endpoint of the fluentd server used by [`ClientBuilder`] when no transport is chosen,
+    /// e.g. `tcp://host:24224`. The constructors taking an address ignore it.
+    /// The default is None.
+    pub endpoint: Option<Endpoint>,
     /// The timeout value to connect to the fluentd server.
     /// The default is 3 seconds.
+    #[serde(deserialize_with = "config::deserialize_duration")]
     pub timeout: Duration,
-    /// The duration of the initial wait for the first retry, in milliseconds.
-    /// The default is 500.
-    pub retry_wait: u64,
-    /// The maximum number of retries. If the number of retries become larger
-    /// than this value, the write/send operation will fail. The default is 10.
-    pub max_retry: u32,
-    /// The maximum duration of wait between retries, in milliseconds.
-    /// If calculated retry wait is larger than this value, operation will fail.
-    /// The default is 60,000 (60 seconds).
-    pub max_retry_wait: u64,
+    /// The policy deciding how long to wait between retries and when to give up a record.
+    /// The default is exponential backoff with full jitter, which waits up to 500 milliseconds
+    /// before the first retry and up to 60 seconds at most, and gives up after 10 retries.
+    #[serde(deserialize_with = "config::deserialize_retry_policy")]
+    pub retry_policy: Arc<dyn RetryPolicy>,
     /// The maximum lifetime of a connection before reconnection is attempted.
     /// Note that reconnection is only triggered when new log lines are sent.
     /// If no new log lines are received within this timeframe, the connection
     /// will remain open, even if it's older than `max_connection_lifetime`.
     /// The default is 0 (no reconnection).
+    #[serde(deserialize_with = "config::deserialize_duration")]
     pub max_connection_lifetime: Duration,
+    /// The prefix joined to the tag of every record with `.`,
+    /// e.g. `myapp` makes the tag `access` into `myapp.access`.
+    /// The default is None.
+    pub tag_prefix: Option<String>,
+    /// What the client does with a tag which does not follow the rules of fluentd tags.
+    /// It is applied to the tag prefix when the client is created, and to the tag of every record.
+    /// The default is [`TagPolicy::Unchecked`], which sends tags as they are.
+    /// Use [`TagPolicy::Validate`] to reject invalid tags with [`Error::InvalidTag`].
+    pub tag_policy: TagPolicy,
+    /// Fields merged into every record sent with a [`Map`], such as `hostname` or `service`.
+    /// Records sent with [`Client::send_encoded`] or [`Client::send_raw`] are not changed.
+    /// The default is empty.
+    pub default_fields: DefaultFields,
+    /// Rules sampling or rate limiting the records by their tags.
+    /// Records are sampled by the tags passed to the client, before anything else is done with them.
+    /// The default is empty (every record is kept).
+    #[serde(skip)]
+    pub sampling: Sampling,
+    /// Filters applied in order to the tag, timestamp and fields of every record sent with a [`Map`],
+    /// before the default fields are merged and the tag prefix is joined.
+    /// A filter can change the record or drop it.
+    /// Records sent with [`Client::send_encoded`] or [`Client::send_raw`] skip the filters.
+    /// The default is empty.
+    #[serde(skip)]
+    pub filters: FilterChain,
+    /// The number of workers, each with its own connection.
+    /// Records are routed to the workers by the hash of their tags,
+    /// so the records of a tag are written in order.
+    /// The default is 1.
+    pub workers: usize,
+    /// The maximum number of records written to a connection whose acks have not been received yet.
+    /// Acks are matched to the records by their chunk ids, and the records still waiting
+    /// for their acks are written again when the connection is replaced.
+    /// A larger value makes better use of the bandwidth of high-latency links.
+    /// The default is 1 (wait for the ack of each record before writing the next one).
+    pub max_in_flight: usize,
+    /// The timeout of writing a record to the connection.
+    /// It catches a server which stops reading, so writes block on a full socket buffer.
+    /// The default is 0 (no timeout).
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub write_timeout: Duration,
+    /// The timeout of waiting for the ack of a record after writing it.
+    /// It catches a server which reads records but stops returning acks,
+    /// and the records waiting for acks are written again on a new connection.
+    /// The default is 0 (no timeout).
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub ack_timeout: Duration,
+    /// The interval of health checks of an idle connection.
+    /// When nothing has been written for this duration, the worker checks whether
+    /// the server has closed the connection or it is broken, and replaces it if so.
+    /// The default is 0 (no health check).
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub health_check_interval: Duration,
+    /// Options of TCP sockets, such as `TCP_NODELAY` and keepalive.
+    /// They are ignored for unix domain sockets.
+    pub tcp: TcpOptions,
+    /// The handler which receives the records the worker failed to send,
+    /// together with the error. It can be a closure or an unbounded channel sender.
+    /// The default is None (failed records are discarded).
+    #[serde(skip)]
+    pub error_handler: Option<Arc<dyn ErrorHandler>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            endpoint: None,
             timeout: Duration::new(3, 0),
-            retry_wait: 500,
-            max_retry: 10,
-            max_retry_wait: 60000,
+            retry_policy: Arc::new(ExponentialBackoff::default()),
             max_connection_lifetime: Duration::from_secs(0),
+            tag_prefix: None,
+            tag_policy: TagPolicy::default(),
+            default_fields: DefaultFields::default(),
+            sampling: Sampling::default(),
+            filters: FilterChain::default(),
+            workers: 1,
+            max_in_flight: 1,
+            write_timeout: Duration::from_secs(0),
+            ack_timeout: Duration::from_secs(0),
+            health_check_interval: Duration::from_secs(0),
+            tcp: TcpOptions::default(),
+            error_handler: None,
+        }
+    }
+}
+
+impl Config {
+    /// Check the config, returning [`Error::InvalidConfig`] or [`Error::InvalidTag`]
+    /// if it is not valid. Clients check their config before connecting.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.timeout.is_zero() {
+            return Err(Error::InvalidConfig("timeout must not be zero".to_string()));
+        }
+        if self.workers == 0 {
+            return Err(Error::InvalidConfig("workers must not be zero".to_string()));
+        }
+        if self.max_in_flight == 0 {
+            return Err(Error::InvalidConfig(
+                "max_in_flight must not be zero".to_string(),
+            ));
+        }
+        if let Some(keepalive) = &self.tcp.keepalive {
+            if keepalive.idle.is_zero() {
+                return Err(Error::InvalidConfig(
+                    "idle of the TCP keepalive must not be zero".to_string(),
+                ));
+            }
+        }
+        if let Some(prefix) = &self.tag_prefix {
+            self.tag_policy.apply(prefix.clone())?;
+        }
+        self.sampling.validate()?;
+        self.retry_policy.validate()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Options of TCP sockets.
+/// A `None` value leaves the OS default.
+pub struct TcpOptions {
+    /// Disable Nagle's algorithm (`TCP_NODELAY`), so small records are sent without delay.
+    /// The default is false.
+    pub nodelay: bool,
+    /// TCP keepalive, which lets the OS detect a dead peer of a half-open connection.
+    pub keepalive: Option<TcpKeepaliveOptions>,
+    /// The size of the send buffer (`SO_SNDBUF`).
+    pub send_buffer_size: Option<u32>,
+    /// The size of the receive buffer (`SO_RCVBUF`).
+    pub recv_buffer_size: Option<u32>,
+    /// The local address to bind before connecting, to choose the network interface.
+    pub local_addr: Option<SocketAddr>,
+    /// How long closing the socket waits for unsent data (`SO_LINGER`).
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub linger: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// TCP keepalive settings.
+pub struct TcpKeepaliveOptions {
+    /// The idle time before the first keepalive probe is sent.
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub idle: Duration,
+    /// The interval between keepalive probes. None leaves the OS default.
+    #[serde(default, deserialize_with = "config::deserialize_optional_duration")]
+    pub interval: Option<Duration>,
+    /// The number of unanswered probes before the connection is dropped. None leaves the OS default.
+    #[serde(default)]
+    pub count: Option<u32>,
+}
+
+impl TcpKeepaliveOptions {
+    /// Send the first probe after `idle`, keeping the OS defaults of the interval and count.
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            interval: None,
+            count: None,
         }
     }
 }
 
 pub trait FluentClient: Send + Sync {
-    fn send(&self, tag: &str, record: Map) -> Result<(), SendError>;
-    fn stop(self) -> Result<(), SendError>;
+    fn send(&self, tag: &str, record: Map) -> Result<(), Error>;
+    fn stop(self) -> Result<(), Error>;
+}
+
+#[async_trait]
+/// An object-safe client trait with async operations.
+///
+/// Unlike [`FluentClient`], every method takes `&self`,
+/// so the client can be shared as `Arc<dyn AsyncFluentClient>`.
+///
+/// ## Example
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use tokio_fluent::client::{AsyncFluentClient, NopClient};
+/// use tokio_fluent::record::Map;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client: Arc<dyn AsyncFluentClient> = Arc::new(NopClient);
+///     client.send("fluent.test", Map::new()).await.unwrap();
+///     client.shutdown().await.unwrap();
+/// }
+/// ```
+pub trait AsyncFluentClient: Send + Sync {
+    /// Send a fluent record, waiting while the queue of the worker is full.
+    async fn send(&self, tag: &str, record: Map) -> Result<(), Error>;
+    /// Wait until all the records sent before are written to the fluentd server or given up.
+    async fn flush(&self) -> Result<(), Error>;
+    /// Flush the records sent before and stop the worker.
+    async fn shutdown(&self) -> Result<(), Error>;
 }
 
 #[derive(Debug, Clone)]
 /// A fluentd client.
 pub struct Client {
-    sender: Sender<Message>,
+    /// The queues of the workers. A record is routed to a worker by the hash of its tag.
+    senders: Vec<Sender<Message>>,
+    metrics: Arc<Metrics>,
+    tag_prefix: Option<String>,
+    tag_policy: TagPolicy,
+    default_fields: Arc<DefaultFields>,
+    filters: Arc<FilterChain>,
+    sampling: Arc<Sampling>,
 }
 
 impl Client {
-    /// Connect to the fluentd server using TCP and create a worker with tokio::spawn.
-    pub async fn new_tcp(addr: SocketAddr, config: &Config) -> AnyhowResult<Client> {
-        let (sender, receiver) = channel(1024);
-
-        let config = config.clone();
-        let stream_config = Arc::new(TCPConnectionConfig {
-            addr: addr.to_owned(),
-            timeout: config.timeout,
-        });
-        // create the worker --
-        // new() will try to establish an connection, so it returns error if connection,
-        // so it returns error upon connection error
-        let mut worker = Worker::new(
-            stream_config,
-            config.max_connection_lifetime,
-            receiver,
-            RetryConfig {
-                initial_wait: config.retry_wait,
-                max: config.max_retry,
-                max_wait: config.max_retry_wait,
-            },
-        )
-        .await?;
-        let _ = tokio::spawn(async move { worker.run().await });
+    /// Create a [`ClientBuilder`] to choose the transport and options of a client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
-        Ok(Self { sender })
+    /// Connect to the fluentd server using TCP and create the workers with tokio::spawn.
+    pub async fn new_tcp(addr: SocketAddr, config: &Config) -> Result<Client, Error> {
+        config.validate()?;
+        Self::open_tcp(addr, config).await
     }
 
-    /// Connect to the fluentd server using unix domain socket and create a worker with tokio::spawn.
+    /// Connect to the fluentd server using unix domain socket and create the workers with tokio::spawn.
     pub async fn new_unix<P: AsRef<Path> + std::marker::Send>(
         path: P,
         config: &Config,
-    ) -> AnyhowResult<Client> {
-        let (sender, receiver) = channel(1024);
+    ) -> Result<Client, Error> {
+        config.validate()?;
+        Self::open_unix(path.as_ref(), config).await
+    }
 
-        let config = config.clone();
-        let stream_config = Arc::new(UnixSocketConfig {
-            path: path.as_ref().to_path_buf(),
+    async fn open_tcp(addr: SocketAddr, config: &Config) -> Result<Client, Error> {
+        let stream_config = Arc::new(TCPConnectionConfig {
+            addr: addr.to_owned(),
             timeout: config.timeout,
+            options: config.tcp.clone(),
         });
-        // create the worker --
-        // new() will try to establish an connection, so it returns error if connection,
-        // so it returns error upon connection error
-        let mut worker = Worker::new(
-            stream_config,
-            config.max_connection_lifetime,
-            receiver,
-            RetryConfig {
-                initial_wait: config.retry_wait,
-                max: config.max_retry,
-                max_wait: config.max_retry_wait,
-            },
-        )
-        .await?;
-        let _ = tokio::spawn(async move {
-            worker.run().await;
+        Self::new_with(stream_config, config).await
+    }
+
+    async fn open_unix(path: &Path, config: &Config) -> Result<Client, Error> {
+        let stream_config = Arc::new(UnixSocketConfig {
+            path: path.to_path_buf(),
+            timeout: config.timeout,
         });
+        Self::new_with(stream_config, config).await
+    }
+
+    /// Connect to the fluentd server at an endpoint URL, such as `tcp://host:24224`
+    /// or `unix:///var/run/fluent.sock`. See [`Endpoint`] for the format.
+    pub async fn connect(url: &str, config: &Config) -> Result<Client, Error> {
+        Self::connect_endpoint(&url.parse()?, config).await
+    }
+
+    /// Connect to the fluentd server at `endpoint`, using the transport it names.
+    ///
+    /// The host of a TCP endpoint is resolved once, and the first address is used
+    /// by all the connections of the client.
+    pub async fn connect_endpoint(endpoint: &Endpoint, config: &Config) -> Result<Client, Error> {
+        config.validate()?;
+        Self::open(endpoint, config).await
+    }
+
+    /// Connect to `endpoint` with a config which is already validated.
+    pub(crate) async fn open(endpoint: &Endpoint, config: &Config) -> Result<Client, Error> {
+        match endpoint {
+            Endpoint::Tcp { host, port } => {
+                let addr = timeout(config.timeout, lookup_host((host.as_str(), *port)))
+                    .await
+                    .map_err(|_| Error::ConnectTimeout(config.timeout))??
+                    .next()
+                    .ok_or_else(|| {
+                        Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("no address found for {}", host),
+                        ))
+                    })?;
+                Self::open_tcp(addr, config).await
+            }
+            Endpoint::Unix(path) => Self::open_unix(path, config).await,
+        }
+    }
+
+    async fn new_with<StreamType>(
+        stream_config: Arc<dyn Connectable<StreamType> + Send + Sync>,
+        config: &Config,
+    ) -> Result<Client, Error>
+    where
+        StreamType: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+    {
+        let tag_prefix = config
+            .tag_prefix
+            .clone()
+            .map(|prefix| config.tag_policy.apply(prefix))
+            .transpose()?;
+        let metrics = Arc::new(Metrics::default());
+        let mut senders = Vec::with_capacity(config.workers);
+        for _ in 0..config.workers {
+            let (sender, receiver) = channel(1024);
+
+            // create the worker --
+            // new() will try to establish an connection, so it returns error if connection,
+            // so it returns error upon connection error
+            let mut worker =
+                Worker::new(stream_config.clone(), receiver, config, metrics.clone()).await?;
+            tokio::spawn(async move { worker.run().await });
+            senders.push(sender);
+        }
+
+        Ok(Self {
+            senders,
+            metrics,
+            tag_prefix,
+            tag_policy: config.tag_policy,
+            default_fields: Arc::new(config.default_fields.clone()),
+            filters: Arc::new(config.filters.clone()),
+            sampling: Arc::new(config.sampling.clone()),
+        })
+    }
+
+    /// Return a snapshot of the statistics of the client and its worker.
+    pub fn stats(&self) -> Stats {
+        let queued: usize = self
+            .senders
+            .iter()
+            .map(|sender| sender.max_capacity() - sender.capacity())
+            .sum();
+        self.metrics.snapshot(queued as u64)
+    }
+
+    /// Send a record body encoded in advance by [`Map::encode`].
+    ///
+    /// Records can be encoded on the threads which produce them,
+    /// and the worker writes the bytes without encoding them again.
+    /// It returns [`Error::QueueFull`] instead of waiting when the queue of the worker is full.
+    ///
+    /// The body is sent as it is: [`Config::filters`] and [`Config::default_fields`]
+    /// are not applied, while sampling and the tag prefix are.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use tokio_fluent::{Client, Config};
+    /// use tokio_fluent::record::Map;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let client = Client::new_tcp("127.0.0.1:24224".parse().unwrap(), &Config::default())
+    ///         .await
+    ///         .unwrap();
+    ///     let mut map = Map::new();
+    ///     map.insert("age".to_string(), 22.into());
+    ///     let encoded = map.encode().unwrap();
+    ///     client.send_encoded("fluent.test", encoded).unwrap();
+    /// }
+    /// ```
+    pub fn send_encoded(&self, tag: &str, encoded: EncodedMap) -> Result<(), Error> {
+        if !self.sample(tag) {
+            return Ok(());
+        }
+        self.try_send_record(Record {
+            tag: self.tag(tag.to_string())?,
+            timestamp: chrono::Local::now().timestamp(),
+            record: Map::new(),
+            encoded: Some(encoded),
+        })
+    }
+
+    /// Send a record body which is already encoded as a MessagePack map,
+    /// such as a body relayed from another fluent client.
+    ///
+    /// `time` is the timestamp of the record in seconds since the Unix epoch.
+    /// Malformed bodies are rejected with [`Error::InvalidRecord`], even when sampling would drop them,
+    /// and the others are queued as with [`Client::send_encoded`],
+    /// so filters and default fields are not applied to them either.
+    pub fn send_raw(&self, tag: &str, time: i64, body: Bytes) -> Result<(), Error> {
+        let encoded = EncodedMap::from_bytes(body)?;
+        if !self.sample(tag) {
+            return Ok(());
+        }
+        self.try_send_record(Record {
+            tag: self.tag(tag.to_string())?,
+            timestamp: time,
+            record: Map::new(),
+            encoded: Some(encoded),
+        })
+    }
 
-        Ok(Self { sender })
+    /// Return true to keep a record of `tag`, counting the records dropped by sampling.
+    fn sample(&self, tag: &str) -> bool {
+        if self.sampling.sample(tag) {
+            return true;
+        }
+        self.metrics.sampled(1);
+        false
     }
 
-    fn send_with_time(&self, tag: &str, record: Map, timestamp: i64) -> Result<(), SendError> {
-        let record = Record {
-            tag: tag.into(),
+    /// Join the tag prefix and `tag`, and apply the tag policy.
+    fn tag(&self, tag: String) -> Result<String, Error> {
+        let tag = match &self.tag_prefix {
+            Some(prefix) => tag::join(prefix, &tag),
+            None => tag,
+        };
+        self.tag_policy.apply(tag)
+    }
+
+    /// Sample the record, apply the filters, merge the default fields and complete the tag.
+    /// Returns None when the record is dropped by sampling or a filter.
+    fn new_record(
+        &self,
+        tag: &str,
+        mut record: Map,
+        mut timestamp: i64,
+    ) -> Result<Option<Record>, Error> {
+        if !self.sample(tag) {
+            return Ok(None);
+        }
+        let mut tag = tag.to_string();
+        if self.filters.apply(&mut tag, &mut timestamp, &mut record) == Decision::Drop {
+            self.metrics.filtered(1);
+            return Ok(None);
+        }
+        self.default_fields.merge_into(&mut record);
+        Ok(Some(Record {
+            tag: self.tag(tag)?,
             record,
             timestamp,
-            options: Options {
-                chunk: general_purpose::STANDARD.encode(Uuid::new_v4()),
-            },
-        };
-        self.sender
-            .send(Message::Record(record))
-            .map_err(|e| SendError {
-                source: e.to_string(),
-            })?;
-        Ok(())
+            encoded: None,
+        }))
+    }
+
+    fn send_with_time(&self, tag: &str, record: Map, timestamp: i64) -> Result<(), Error> {
+        match self.new_record(tag, record, timestamp)? {
+            Some(record) => self.try_send_record(record),
+            None => Ok(()),
+        }
+    }
+
+    fn try_send_record(&self, record: Record) -> Result<(), Error> {
+        match self.sender(&record.tag).try_send(Message::Record(record)) {
+            Ok(()) => {
+                self.metrics.enqueued(1);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.metrics.dropped_overflow(1);
+                Err(Error::QueueFull)
+            }
+            Err(TrySendError::Closed(_)) => Err(Error::WorkerTerminated),
+        }
+    }
+
+    /// The queue of the worker which the records of `tag` are routed to,
+    /// so the records of a tag are written in order.
+    fn sender(&self, tag: &str) -> &Sender<Message> {
+        if self.senders.len() == 1 {
+            return &self.senders[0];
+        }
+        let mut hasher = DefaultHasher::new();
+        tag.hash(&mut hasher);
+        &self.senders[hasher.finish() as usize % self.senders.len()]
+    }
+
+    async fn send_message(sender: &Sender<Message>, message: Message) -> Result<(), Error> {
+        sender
+            .send(message)
+            .await
+            .map_err(|_| Error::WorkerTerminated)
     }
 }
 
@@ -183,25 +569,67 @@ impl FluentClient for Client {
     /// `tag` - Event category of a record to send.
     ///
     /// `record` - Map object to send as a fluent record.
-    fn send(&self, tag: &str, record: Map) -> Result<(), SendError> {
+    ///
+    /// This fails without waiting when the queue of the worker is full.
+    fn send(&self, tag: &str, record: Map) -> Result<(), Error> {
         self.send_with_time(tag, record, chrono::Local::now().timestamp())
     }
 
-    /// Stop the worker.
-    fn stop(self) -> Result<(), SendError> {
-        self.sender
-            .send(Message::Terminate)
-            .map_err(|e| SendError {
-                source: e.to_string(),
-            })?;
+    /// Stop the workers.
+    fn stop(self) -> Result<(), Error> {
+        self.senders.iter().try_for_each(|sender| {
+            sender.try_send(Message::Terminate).map_err(|e| match e {
+                TrySendError::Full(_) => Error::QueueFull,
+                TrySendError::Closed(_) => Error::WorkerTerminated,
+            })
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncFluentClient for Client {
+    /// Send a fluent record to the fluentd server.
+    /// Unlike [`FluentClient::send`], this waits for a free slot when the queue is full.
+    async fn send(&self, tag: &str, record: Map) -> Result<(), Error> {
+        let Some(record) = self.new_record(tag, record, chrono::Local::now().timestamp())? else {
+            return Ok(());
+        };
+        Self::send_message(self.sender(&record.tag), Message::Record(record)).await?;
+        self.metrics.enqueued(1);
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let mut waits = Vec::with_capacity(self.senders.len());
+        for sender in &self.senders {
+            let (done, wait) = oneshot::channel();
+            Self::send_message(sender, Message::Flush(done)).await?;
+            waits.push(wait);
+        }
+        for wait in waits {
+            wait.await.map_err(|_| Error::WorkerTerminated)?;
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), Error> {
+        AsyncFluentClient::flush(self).await?;
+        for sender in &self.senders {
+            Self::send_message(sender, Message::Terminate).await?;
+        }
+        for sender in &self.senders {
+            sender.closed().await;
+        }
         Ok(())
     }
 }
 
-/// The worker is terminated when client is dropped.
+/// The workers are terminated when client is dropped.
 impl Drop for Client {
     fn drop(&mut self) {
-        let _ = self.sender.send(Message::Terminate);
+        for sender in &self.senders {
+            let _ = sender.try_send(Message::Terminate);
+        }
     }
 }
 
@@ -210,11 +638,157 @@ impl Drop for Client {
 pub struct NopClient;
 
 impl FluentClient for NopClient {
-    fn send(&self, _tag: &str, _record: Map) -> Result<(), SendError> {
+    fn send(&self, _tag: &str, _record: Map) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn stop(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsyncFluentClient for NopClient {
+    async fn send(&self, _tag: &str, _record: Map) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A record captured by [`MemoryClient`].
+pub struct CapturedRecord {
+    pub tag: String,
+    pub record: Map,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+/// MemoryClient keeps every record in memory instead of sending it.
+///
+/// Clones share the same buffer, so a clone can be handed to the code under test
+/// and the original can be used for assertions.
+///
+/// ## Example
+///
+/// ```
+/// use tokio_fluent::client::MemoryClient;
+/// use tokio_fluent::record::Map;
+/// use tokio_fluent::FluentClient;
+///
+/// let client = MemoryClient::new();
+/// let mut map = Map::new();
+/// map.insert("age".to_string(), 10.into());
+/// client.send("fluent.test", map.clone()).unwrap();
+///
+/// client.assert_sent("fluent.test", &map);
+/// assert_eq!(client.records_with_tag("fluent.test"), vec![map]);
+/// ```
+pub struct MemoryClient {
+    records: Arc<Mutex<Vec<CapturedRecord>>>,
+}
+
+impl MemoryClient {
+    /// Create a client with an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All the records sent so far, in order of sending.
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// The maps of the records sent with `tag`, in order of sending.
+    pub fn records_with_tag(&self, tag: &str) -> Vec<Map> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.tag == tag)
+            .map(|r| r.record.clone())
+            .collect()
+    }
+
+    /// The number of records sent so far.
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    /// Whether no record has been sent.
+    pub fn is_empty(&self) -> bool {
+        self.records.lock().unwrap().is_empty()
+    }
+
+    /// Remove all the records sent so far.
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    /// Panic unless a record equal to `record` has been sent with `tag`.
+    #[track_caller]
+    pub fn assert_sent(&self, tag: &str, record: &Map) {
+        let records = self.records();
+        assert!(
+            records.iter().any(|r| r.tag == tag && &r.record == record),
+            "record {:?} with tag {:?} was not sent. sent records: {:?}",
+            record,
+            tag,
+            records
+        );
+    }
+
+    /// Panic if any record has been sent with `tag`.
+    #[track_caller]
+    pub fn assert_not_sent(&self, tag: &str) {
+        let records = self.records_with_tag(tag);
+        assert!(
+            records.is_empty(),
+            "records with tag {:?} were sent: {:?}",
+            tag,
+            records
+        );
+    }
+
+    fn send_with_time(&self, tag: &str, record: Map, timestamp: i64) {
+        self.records.lock().unwrap().push(CapturedRecord {
+            tag: tag.to_string(),
+            record,
+            timestamp,
+        });
+    }
+}
+
+impl FluentClient for MemoryClient {
+    fn send(&self, tag: &str, record: Map) -> Result<(), Error> {
+        self.send_with_time(tag, record, chrono::Local::now().timestamp());
         Ok(())
     }
 
-    fn stop(self) -> Result<(), SendError> {
+    fn stop(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsyncFluentClient for MemoryClient {
+    async fn send(&self, tag: &str, record: Map) -> Result<(), Error> {
+        self.send_with_time(tag, record, chrono::Local::now().timestamp());
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -223,6 +797,18 @@ impl FluentClient for NopClient {
 mod tests {
     use super::*;
 
+    fn client_with_sender(sender: Sender<Message>) -> Client {
+        Client {
+            senders: vec![sender],
+            metrics: Default::default(),
+            tag_prefix: None,
+            tag_policy: TagPolicy::default(),
+            default_fields: Default::default(),
+            filters: Default::default(),
+            sampling: Default::default(),
+        }
+    }
+
     #[test]
     fn test_send_with_time() {
         use std::collections::HashMap;
@@ -233,7 +819,7 @@ mod tests {
         use crate::record_map;
 
         let (sender, mut receiver) = channel(1024);
-        let client = Client { sender };
+        let client = client_with_sender(sender);
 
         let timestamp = chrono::Utc.timestamp_opt(1234567, 0).unwrap().timestamp();
         let record = record_map!("age".to_string() => 20.into());
@@ -249,19 +835,186 @@ mod tests {
                 assert_eq!(r.record, record_map!("age".to_string() => 20.into()));
                 assert_eq!(r.timestamp, 1234567);
             }
+            Message::Flush(_) => unreachable!("got flush message"),
             Message::Terminate => unreachable!("got terminate message"),
         }
     }
 
+    #[tokio::test]
+    async fn test_tag_prefix_and_policy() {
+        let (sender, mut receiver) = channel(1024);
+        let mut client = client_with_sender(sender);
+        client.tag_prefix = Some("myapp".to_string());
+        client
+            .send_with_time("my_crate::module", Map::new(), 0)
+            .unwrap();
+
+        client.tag_policy = TagPolicy::Validate;
+        client.send_with_time("access", Map::new(), 0).unwrap();
+        assert!(matches!(
+            client.send_with_time("", Map::new(), 0),
+            Err(Error::InvalidTag(tag)) if tag == "myapp."
+        ));
+        assert!(matches!(
+            client.send_with_time("bad tag", Map::new(), 0),
+            Err(Error::InvalidTag(tag)) if tag == "myapp.bad tag"
+        ));
+        assert!(matches!(
+            AsyncFluentClient::send(&client, "bad..tag", Map::new()).await,
+            Err(Error::InvalidTag(_))
+        ));
+        client.tag_policy = TagPolicy::Sanitize;
+        client.send_with_time("bad tag", Map::new(), 0).unwrap();
+        client.send_with_time("", Map::new(), 0).unwrap();
+
+        let mut tags = Vec::new();
+        while let Ok(Message::Record(record)) = receiver.try_recv() {
+            tags.push(record.tag);
+        }
+        assert_eq!(
+            tags,
+            [
+                "myapp.my_crate::module",
+                "myapp.access",
+                "myapp.bad_tag",
+                "myapp"
+            ]
+        );
+        assert_eq!(client.stats().enqueued, 4);
+
+        let config = Config {
+            tag_prefix: Some("my app".to_string()),
+            tag_policy: TagPolicy::Validate,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Client::new_tcp("127.0.0.1:1".parse().unwrap(), &config).await,
+            Err(Error::InvalidTag(_))
+        ));
+    }
+
+    #[test]
+    fn test_default_fields() {
+        use crate::fields::ConflictPolicy;
+
+        let (sender, mut receiver) = channel(1024);
+        let mut client = client_with_sender(sender);
+        client.default_fields = Arc::new(
+            DefaultFields::new()
+                .insert("service", "billing")
+                .insert("env", "production")
+                .with_conflict_policy(ConflictPolicy::KeepRecord),
+        );
+
+        let mut map = Map::new();
+        map.insert("env".to_string(), "staging".into());
+        client.send_with_time("test", map, 0).unwrap();
+        client
+            .send_encoded("test", Map::new().encode().unwrap())
+            .unwrap();
+
+        match receiver.try_recv().expect("failed to receive") {
+            Message::Record(r) => {
+                assert_eq!(r.record["service"], "billing".into());
+                assert_eq!(r.record["env"], "staging".into());
+            }
+            _ => unreachable!("got a message other than record"),
+        }
+        match receiver.try_recv().expect("failed to receive") {
+            Message::Record(r) => assert!(r.record.is_empty()),
+            _ => unreachable!("got a message other than record"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sampling() {
+        use crate::sampling::{OneInN, Probabilistic};
+
+        let (sender, mut receiver) = channel(1024);
+        let mut client = client_with_sender(sender);
+        client.sampling = Arc::new(
+            Sampling::new()
+                .rule("debug.**", Probabilistic::new(0.0))
+                .rule("app", OneInN::new(2)),
+        );
+
+        for _ in 0..4 {
+            client.send_with_time("app", Map::new(), 0).unwrap();
+        }
+        client.send_with_time("debug.sql", Map::new(), 0).unwrap();
+        AsyncFluentClient::send(&client, "debug", Map::new())
+            .await
+            .unwrap();
+        client
+            .send_encoded("debug", Map::new().encode().unwrap())
+            .unwrap();
+        client
+            .send_raw("debug", 0, Bytes::from_static(b"\x80"))
+            .unwrap();
+        assert!(matches!(
+            client.send_raw("debug", 0, Bytes::new()),
+            Err(Error::InvalidRecord(_))
+        ));
+        client.send_with_time("other", Map::new(), 0).unwrap();
+
+        let mut tags = Vec::new();
+        while let Ok(Message::Record(record)) = receiver.try_recv() {
+            tags.push(record.tag);
+        }
+        assert_eq!(tags, ["app", "app", "other"]);
+        let stats = client.stats();
+        assert_eq!((stats.enqueued, stats.sampled), (3, 6));
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        use crate::filter::Redact;
+
+        let (sender, mut receiver) = channel(1024);
+        let mut client = client_with_sender(sender);
+        client.tag_prefix = Some("myapp".to_string());
+        client.filters = Arc::new(FilterChain::new().with(Redact::new(["password"])).with(
+            |tag: &mut String, _: &mut i64, _: &mut Map| {
+                if tag == "debug" {
+                    return Decision::Drop;
+                }
+                if tag == "old" {
+                    *tag = "new".to_string();
+                }
+                Decision::Keep
+            },
+        ));
+
+        let mut map = Map::new();
+        map.insert("password".to_string(), "secret".into());
+        client.send_with_time("old", map, 0).unwrap();
+        client.send_with_time("debug", Map::new(), 0).unwrap();
+        AsyncFluentClient::send(&client, "debug", Map::new())
+            .await
+            .unwrap();
+
+        match receiver.try_recv().expect("failed to receive") {
+            Message::Record(r) => {
+                assert_eq!(r.tag, "myapp.new");
+                assert_eq!(r.record["password"], "[REDACTED]".into());
+            }
+            _ => unreachable!("got a message other than record"),
+        }
+        assert!(receiver.try_recv().is_err());
+        let stats = client.stats();
+        assert_eq!((stats.enqueued, stats.filtered), (1, 2));
+    }
+
     #[test]
     fn test_stop() {
         let (sender, mut receiver) = channel(1024);
-        let client = Client { sender };
+        let client = client_with_sender(sender);
         assert!(client.stop().is_ok(), "faled to stop");
 
         let got = receiver.try_recv().expect("failed to receive");
         match got {
             Message::Record(_) => unreachable!("got record message"),
+            Message::Flush(_) => unreachable!("got flush message"),
             Message::Terminate => {}
         };
     }
@@ -270,21 +1023,296 @@ mod tests {
     fn test_client_drop_sends_terminate() {
         let (sender, mut receiver) = channel(1024);
         {
-            Client { sender };
+            client_with_sender(sender);
         }
         let got = receiver.try_recv().expect("failed to receive");
         match got {
             Message::Record(_) => unreachable!("got record message"),
+            Message::Flush(_) => unreachable!("got flush message"),
             Message::Terminate => {}
         };
     }
 
+    #[test]
+    fn test_memory_client() {
+        use std::collections::HashMap;
+
+        use crate::record::Value;
+        use crate::record_map;
+
+        let client = MemoryClient::new();
+        let cloned = client.clone();
+        assert!(client.is_empty());
+
+        cloned.send_with_time(
+            "test.a",
+            record_map!("age".to_string() => 1.into()),
+            1234567,
+        );
+        FluentClient::send(
+            &cloned,
+            "test.b",
+            record_map!("age".to_string() => 2.into()),
+        )
+        .unwrap();
+
+        assert_eq!(client.len(), 2);
+        assert_eq!(
+            client.records()[0],
+            CapturedRecord {
+                tag: "test.a".to_string(),
+                record: record_map!("age".to_string() => 1.into()),
+                timestamp: 1234567,
+            }
+        );
+        assert_eq!(
+            client.records_with_tag("test.b"),
+            vec![record_map!("age".to_string() => 2.into())]
+        );
+        client.assert_sent("test.a", &record_map!("age".to_string() => 1.into()));
+        client.assert_not_sent("test.c");
+
+        client.clear();
+        assert!(client.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "was not sent")]
+    fn test_memory_client_assert_sent_fails() {
+        let client = MemoryClient::new();
+        FluentClient::send(&client, "test", Map::new()).unwrap();
+        client.assert_sent("other", &Map::new());
+    }
+
+    #[tokio::test]
+    async fn test_async_send_and_flush() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        for i in 0..10 {
+            let mut map = Map::new();
+            map.insert("i".to_string(), i.into());
+            AsyncFluentClient::send(&client, "test", map).await.unwrap();
+        }
+        AsyncFluentClient::flush(&client).await.unwrap();
+        assert_eq!(server.received().len(), 10);
+
+        client.shutdown().await.unwrap();
+        assert!(AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_encoded() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        let mut map = Map::new();
+        map.insert("age".to_string(), 22.into());
+        client.send_encoded("test", map.encode().unwrap()).unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, "test");
+        assert_eq!(received[0].2, map);
+    }
+
+    #[tokio::test]
+    async fn test_send_raw() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        client
+            .send_raw(
+                "test",
+                1_700_000_000,
+                Bytes::from_static(b"\x81\xa3age\x16"),
+            )
+            .unwrap();
+        assert!(matches!(
+            client.send_raw("test", 1_700_000_000, Bytes::from_static(b"\x81\xa3age")),
+            Err(Error::InvalidRecord(_))
+        ));
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        let mut map = Map::new();
+        map.insert("age".to_string(), 22.into());
+        assert_eq!(
+            server.received(),
+            vec![("test".to_string(), 1_700_000_000, map)]
+        );
+        assert_eq!(client.stats().enqueued, 1);
+    }
+
+    #[tokio::test]
+    async fn test_workers() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let config = Config {
+            workers: 4,
+            ..Default::default()
+        };
+        let client = Client::new_tcp(server.local_addr().unwrap(), &config)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while server.connections() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("workers did not connect");
+
+        for i in 0..10 {
+            for tag in 0..8 {
+                let mut map = Map::new();
+                map.insert("i".to_string(), i.into());
+                AsyncFluentClient::send(&client, &format!("test.{}", tag), map)
+                    .await
+                    .unwrap();
+            }
+        }
+        AsyncFluentClient::flush(&client).await.unwrap();
+        assert_eq!(client.stats().acked, 80);
+
+        let received = server.received();
+        for tag in 0..8 {
+            let tag = format!("test.{}", tag);
+            let got: Vec<_> = received
+                .iter()
+                .filter(|(t, _, _)| *t == tag)
+                .map(|(_, _, map)| map["i"].clone())
+                .collect();
+            let want: Vec<_> = (0..10).map(|i| i.into()).collect();
+            assert_eq!(got, want);
+        }
+
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        FluentClient::send(&client, "test", Map::new()).unwrap();
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        let stats = client.stats();
+        assert_eq!(stats.enqueued, 2);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.acked, 2);
+        assert_eq!(stats.retried, 0);
+        assert_eq!(stats.failed, 0);
+        assert!(stats.bytes_written > 0);
+        assert_eq!(stats.ack_latency.count, 2);
+    }
+
+    #[test]
+    fn test_stats_dropped_overflow() {
+        let (sender, _receiver) = channel(1);
+        let client = client_with_sender(sender);
+
+        assert!(client.send_with_time("test", Map::new(), 0).is_ok());
+        assert!(matches!(
+            client.send_with_time("test", Map::new(), 0),
+            Err(Error::QueueFull)
+        ));
+
+        let stats = client.stats();
+        assert_eq!(stats.enqueued, 1);
+        assert_eq!(stats.queued, 1);
+        assert_eq!(stats.dropped_overflow, 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_client_trait_object() {
+        let memory = MemoryClient::new();
+        let client: Arc<dyn AsyncFluentClient> = Arc::new(memory.clone());
+
+        client.send("test", Map::new()).await.unwrap();
+        client.flush().await.unwrap();
+        client.shutdown().await.unwrap();
+        memory.assert_sent("test", &Map::new());
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        use crate::testing::MockFluentServer;
+
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let url = format!("tcp://127.0.0.1:{}", server.local_addr().unwrap().port());
+        let client = Client::connect(&url, &Config::default()).await.unwrap();
+        AsyncFluentClient::send(&client, "tcp", Map::new())
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("tokio-fluent-{:016x}.sock", fastrand::u64(..)));
+        let unix_server = MockFluentServer::bind_unix(&path).await.unwrap();
+        let url = format!("unix://{}", path.display());
+        let client = Client::connect(&url, &Config::default()).await.unwrap();
+        AsyncFluentClient::send(&client, "unix", Map::new())
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        assert_eq!(server.received()[0].0, "tcp");
+        assert_eq!(unix_server.received()[0].0, "unix");
+
+        for url in ["tls://localhost", "udp://localhost:24224"] {
+            assert!(matches!(
+                Client::connect(url, &Config::default()).await,
+                Err(Error::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_error() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        match Client::new_tcp(addr, &Config::default()).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused),
+            _ => unreachable!("connected to a closed port"),
+        }
+    }
+
     #[test]
     fn test_default_config() {
         let config: Config = Default::default();
         assert_eq!(config.timeout, Duration::new(3, 0));
-        assert_eq!(config.retry_wait, 500);
-        assert_eq!(config.max_retry, 10);
-        assert_eq!(config.max_retry_wait, 60000);
+        let policy = format!("{:?}", config.retry_policy);
+        assert!(policy.starts_with("ExponentialBackoff"), "{}", policy);
+        assert!(
+            config.retry_policy.next_wait(10, Duration::ZERO).unwrap() <= Duration::from_secs(60)
+        );
+        assert!(config.retry_policy.next_wait(11, Duration::ZERO).is_none());
     }
 }
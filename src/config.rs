@@ -0,0 +1,397 @@
+//! Loading [`Config`] from environment variables and files.
+//!
+//! [`Config`] implements `serde::Deserialize`, so it can be read from TOML or any other format
+//! supported by serde. Durations are written in the humantime format, e.g. `"500ms"`, `"3s"`
+//! or `"1m 30s"`, and the fields which are not given keep their defaults.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::{Error as _, IntoDeserializer};
+use serde::{Deserialize, Deserializer};
+
+use crate::client::Config;
+use crate::endpoint::{Endpoint, DEFAULT_PORT};
+use crate::error::Error;
+use crate::retry::{ExponentialBackoff, RetryPolicy};
+use crate::tag::TagPolicy;
+
+impl Config {
+    /// Read a config from the environment variables whose names start with `prefix` and `_`.
+    ///
+    /// With the prefix `FLUENT`, the variables are:
+    ///
+    /// | Variable | Field |
+    /// | -------- | ----- |
+    /// | `FLUENT_URL` | `endpoint`, e.g. `tcp://host:24224` or `unix:///var/run/fluent.sock` |
+    /// | `FLUENT_HOST`, `FLUENT_PORT` | `endpoint` using TCP, when `FLUENT_URL` is not set |
+    /// | `FLUENT_SOCKET_PATH` | `endpoint` using unix domain socket, when `FLUENT_URL` is not set |
+    /// | `FLUENT_TIMEOUT` | `timeout` |
+    /// | `FLUENT_RETRY_WAIT`, `FLUENT_MAX_RETRY_WAIT`, `FLUENT_MAX_RETRIES` | `retry_policy`, as [`ExponentialBackoff`]; `FLUENT_MAX_RETRIES=forever` never gives up |
+    /// | `FLUENT_MAX_CONNECTION_LIFETIME` | `max_connection_lifetime` |
+    /// | `FLUENT_TAG_PREFIX` | `tag_prefix` |
+    /// | `FLUENT_TAG_POLICY` | `tag_policy`: `validate`, `sanitize` or `unchecked` |
+    /// | `FLUENT_WORKERS` | `workers` |
+    /// | `FLUENT_MAX_IN_FLIGHT` | `max_in_flight` |
+    /// | `FLUENT_WRITE_TIMEOUT` | `write_timeout` |
+    /// | `FLUENT_ACK_TIMEOUT` | `ack_timeout` |
+    /// | `FLUENT_HEALTH_CHECK_INTERVAL` | `health_check_interval` |
+    ///
+    /// Durations are written in the humantime format, e.g. `3s`. Unset and empty variables
+    /// keep the defaults, and a value which cannot be parsed is an [`Error::InvalidConfig`].
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use tokio_fluent::{ClientBuilder, Config};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let config = Config::from_env("FLUENT").unwrap();
+    ///     let client = ClientBuilder::with_config(config).build().await.unwrap();
+    /// }
+    /// ```
+    pub fn from_env(prefix: &str) -> Result<Config, Error> {
+        Self::from_lookup(prefix, |name| std::env::var(name).ok())
+    }
+
+    fn from_lookup<F>(prefix: &str, lookup: F) -> Result<Config, Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let vars = Vars { prefix, lookup };
+        let mut config = Config::default();
+
+        if let Some(url) = vars.get("URL") {
+            config.endpoint = Some(url.parse().map_err(|e| match e {
+                Error::InvalidConfig(reason) => vars.invalid("URL", reason),
+                e => e,
+            })?);
+        } else if let Some(path) = vars.get("SOCKET_PATH") {
+            config.endpoint = Some(Endpoint::Unix(path.into()));
+        } else if vars.get("HOST").is_some() || vars.get("PORT").is_some() {
+            config.endpoint = Some(Endpoint::Tcp {
+                host: vars.get("HOST").unwrap_or_else(|| "127.0.0.1".to_string()),
+                port: vars.parse("PORT")?.unwrap_or(DEFAULT_PORT),
+            });
+        }
+
+        if let Some(timeout) = vars.duration("TIMEOUT")? {
+            config.timeout = timeout;
+        }
+        let retry_wait = vars.duration("RETRY_WAIT")?;
+        let max_retry_wait = vars.duration("MAX_RETRY_WAIT")?;
+        let max_retries = vars
+            .get("MAX_RETRIES")
+            .map(|value| parse_max_retries(&value).map_err(|e| vars.invalid("MAX_RETRIES", e)))
+            .transpose()?;
+        if retry_wait.is_some() || max_retry_wait.is_some() || max_retries.is_some() {
+            let default = ExponentialBackoff::default();
+            let policy = ExponentialBackoff::new(
+                retry_wait.unwrap_or(default.initial()),
+                max_retry_wait.unwrap_or(default.max_wait()),
+            );
+            config.retry_policy = Arc::new(match max_retries.unwrap_or(default.max_retries()) {
+                Some(max_retries) => policy.with_max_retries(max_retries),
+                None => policy.forever(),
+            });
+        }
+        if let Some(lifetime) = vars.duration("MAX_CONNECTION_LIFETIME")? {
+            config.max_connection_lifetime = lifetime;
+        }
+        if let Some(tag_prefix) = vars.get("TAG_PREFIX") {
+            config.tag_prefix = Some(tag_prefix);
+        }
+        if let Some(tag_policy) = vars.get("TAG_POLICY") {
+            let deserializer = tag_policy.as_str().into_deserializer();
+            config.tag_policy = TagPolicy::deserialize(deserializer)
+                .map_err(|e: serde::de::value::Error| vars.invalid("TAG_POLICY", e))?;
+        }
+        if let Some(workers) = vars.parse("WORKERS")? {
+            config.workers = workers;
+        }
+        if let Some(max_in_flight) = vars.parse("MAX_IN_FLIGHT")? {
+            config.max_in_flight = max_in_flight;
+        }
+        if let Some(timeout) = vars.duration("WRITE_TIMEOUT")? {
+            config.write_timeout = timeout;
+        }
+        if let Some(timeout) = vars.duration("ACK_TIMEOUT")? {
+            config.ack_timeout = timeout;
+        }
+        if let Some(interval) = vars.duration("HEALTH_CHECK_INTERVAL")? {
+            config.health_check_interval = interval;
+        }
+        Ok(config)
+    }
+}
+
+/// Environment variables sharing a prefix.
+struct Vars<'a, F> {
+    prefix: &'a str,
+    lookup: F,
+}
+
+impl<F> Vars<'_, F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn name(&self, key: &str) -> String {
+        format!("{}_{}", self.prefix, key)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        (self.lookup)(&self.name(key)).filter(|value| !value.is_empty())
+    }
+
+    fn parse<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get(key)
+            .map(|value| value.parse().map_err(|e| self.invalid(key, e)))
+            .transpose()
+    }
+
+    fn duration(&self, key: &str) -> Result<Option<Duration>, Error> {
+        self.get(key)
+            .map(|value| humantime::parse_duration(&value).map_err(|e| self.invalid(key, e)))
+            .transpose()
+    }
+
+    fn invalid(&self, key: &str, e: impl std::fmt::Display) -> Error {
+        Error::InvalidConfig(format!("{}: {}", self.name(key), e))
+    }
+}
+
+/// Deserialize a duration in the humantime format, e.g. `"3s"`.
+pub(crate) fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    humantime::parse_duration(&value).map_err(D::Error::custom)
+}
+
+/// Deserialize an optional duration in the humantime format.
+pub(crate) fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| humantime::parse_duration(&value).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// Parse a maximum number of retries, or `forever` for None.
+fn parse_max_retries(value: &str) -> Result<Option<u32>, String> {
+    match value {
+        "forever" => Ok(None),
+        _ => value.parse().map(Some).map_err(|_| {
+            format!(
+                "expected a number of retries or \"forever\", got {:?}",
+                value
+            )
+        }),
+    }
+}
+
+/// Deserialize a maximum number of retries, written as a number or `"forever"`.
+pub(crate) fn deserialize_max_retries<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    struct Visitor;
+
+    impl serde::de::Visitor<'_> for Visitor {
+        type Value = Option<u32>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("a number of retries or \"forever\"")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            u32::try_from(value).map(Some).map_err(E::custom)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            u32::try_from(value).map(Some).map_err(E::custom)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            parse_max_retries(value).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
+
+/// Deserialize the parameters of [`ExponentialBackoff`] as a retry policy.
+pub(crate) fn deserialize_retry_policy<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Arc<dyn RetryPolicy>, D::Error> {
+    Ok(Arc::new(ExponentialBackoff::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::record::Value;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Config, Error> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::from_lookup("FLUENT", |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_env() {
+        let config = from_vars(&[
+            ("FLUENT_HOST", "fluentd.local"),
+            ("FLUENT_TIMEOUT", "5s"),
+            ("FLUENT_RETRY_WAIT", "100ms"),
+            ("FLUENT_MAX_RETRY_WAIT", "10s"),
+            ("FLUENT_TAG_PREFIX", "myapp"),
+            ("FLUENT_TAG_POLICY", "sanitize"),
+            ("FLUENT_WORKERS", "4"),
+            ("FLUENT_ACK_TIMEOUT", "1m 30s"),
+            ("FLUENT_WRITE_TIMEOUT", ""),
+            ("FLUENT_HEALTH_CHECK_INTERVAL", "0"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.endpoint,
+            Some(Endpoint::Tcp {
+                host: "fluentd.local".to_string(),
+                port: 24224
+            })
+        );
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        let policy = format!("{:?}", config.retry_policy);
+        assert!(
+            policy.contains("initial: 100ms, max_wait: 10s"),
+            "{}",
+            policy
+        );
+        assert!(policy.contains("max_retries: Some(10)"), "{}", policy);
+        assert_eq!(config.tag_prefix.as_deref(), Some("myapp"));
+        assert_eq!(config.tag_policy, TagPolicy::Sanitize);
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.ack_timeout, Duration::from_secs(90));
+        assert_eq!(config.write_timeout, Duration::ZERO);
+        assert_eq!(config.health_check_interval, Duration::ZERO);
+
+        let config = from_vars(&[
+            ("FLUENT_URL", "unix:///var/run/fluent.sock"),
+            ("FLUENT_HOST", "ignored"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.endpoint,
+            Some(Endpoint::Unix("/var/run/fluent.sock".into()))
+        );
+        assert_eq!(from_vars(&[]).unwrap().endpoint, None);
+
+        let config = from_vars(&[("FLUENT_MAX_RETRIES", "forever")]).unwrap();
+        let policy = format!("{:?}", config.retry_policy);
+        assert!(policy.contains("max_retries: None"), "{}", policy);
+    }
+
+    #[test]
+    fn test_from_env_errors() {
+        for (name, value) in [
+            ("FLUENT_PORT", "http"),
+            ("FLUENT_TIMEOUT", "3"),
+            ("FLUENT_WORKERS", "-1"),
+            ("FLUENT_MAX_RETRIES", "always"),
+            ("FLUENT_TAG_POLICY", "strict"),
+            ("FLUENT_URL", "fluentd:24224"),
+            ("FLUENT_URL", "tls://fluentd"),
+        ] {
+            match from_vars(&[(name, value)]) {
+                Err(Error::InvalidConfig(reason)) => {
+                    assert!(reason.starts_with(name), "{}", reason)
+                }
+                other => panic!("{}={}: {:?}", name, value, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config = toml::from_str(
+            r#"
+            endpoint = "tcp://fluentd.local:24225"
+            timeout = "5s"
+            tag_prefix = "myapp"
+            tag_policy = "unchecked"
+            workers = 2
+            health_check_interval = "30s"
+
+            [retry_policy]
+            initial = "100ms"
+            max_wait = "10s"
+            max_retries = 3
+
+            [default_fields]
+            service = "billing"
+
+            [tcp]
+            nodelay = true
+            linger = "1s"
+            keepalive = { idle = "60s", count = 3 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.endpoint,
+            Some(Endpoint::Tcp {
+                host: "fluentd.local".to_string(),
+                port: 24225
+            })
+        );
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert_eq!(config.tag_prefix.as_deref(), Some("myapp"));
+        assert_eq!(config.tag_policy, TagPolicy::Unchecked);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.max_in_flight, 1);
+        assert_eq!(config.health_check_interval, Duration::from_secs(30));
+        let policy = format!("{:?}", config.retry_policy);
+        assert!(policy.contains("max_retries: Some(3)"), "{}", policy);
+
+        let mut map = crate::record::Map::new();
+        config.default_fields.merge_into(&mut map);
+        assert_eq!(map["service"], Value::from("billing"));
+
+        assert!(config.tcp.nodelay);
+        assert_eq!(config.tcp.linger, Some(Duration::from_secs(1)));
+        let keepalive = config.tcp.keepalive.unwrap();
+        assert_eq!(keepalive.idle, Duration::from_secs(60));
+        assert_eq!((keepalive.interval, keepalive.count), (None, Some(3)));
+    }
+
+    #[test]
+    fn test_deserialize_forever() {
+        let config: Config = toml::from_str("[retry_policy]\nmax_retries = \"forever\"").unwrap();
+        let policy = format!("{:?}", config.retry_policy);
+        assert!(policy.contains("max_retries: None"), "{}", policy);
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        for file in [
+            "timeout = 3",
+            "timeout = \"3 parsecs\"",
+            "endpoint = \"udp://localhost\"",
+            "unknown = 1",
+            "[retry_policy]\nwait = \"1s\"",
+            "[retry_policy]\nmax_retries = \"always\"",
+            "[retry_policy]\nmax_retries = -1",
+        ] {
+            assert!(toml::from_str::<Config>(file).is_err(), "{}", file);
+        }
+    }
+}
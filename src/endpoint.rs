@@ -0,0 +1,200 @@
+//! Endpoints of fluentd servers given as strings.
+//!
+//! An [`Endpoint`] can be parsed from a URL, so the transport can be chosen by
+//! an environment variable or a config file instead of code:
+//!
+//! - `tcp://host:24224`, where the port defaults to 24224
+//! - `unix:///var/run/fluent.sock`, with an absolute path
+//!
+//! Other schemes, including `tls://`, are rejected as unsupported.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use tokio_fluent::{Client, Config};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let url = std::env::var("FLUENT_URL").unwrap_or("tcp://127.0.0.1:24224".to_string());
+//!     let client = Client::connect(&url, &Config::default()).await.unwrap();
+//! }
+//! ```
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::error::Error;
+
+/// The default port of the forward protocol.
+pub const DEFAULT_PORT: u16 = 24224;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The transport and address of a fluentd server.
+pub enum Endpoint {
+    /// TCP. The host is a name or an IP address, resolved when the client connects.
+    Tcp { host: String, port: u16 },
+    /// Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Endpoint::Tcp {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| Error::InvalidConfig(format!("endpoint {:?}: {}", url, reason));
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return Err(invalid("missing scheme, such as tcp://"));
+        };
+        if rest.contains('?') {
+            return Err(invalid("query parameters are not supported"));
+        }
+
+        match scheme {
+            "tcp" => {
+                let (host, port) = parse_authority(rest, DEFAULT_PORT).map_err(invalid)?;
+                Ok(Endpoint::Tcp { host, port })
+            }
+            "unix" => {
+                if !rest.starts_with('/') {
+                    return Err(invalid("the path must be absolute, as in unix:///path"));
+                }
+                Ok(Endpoint::Unix(PathBuf::from(rest)))
+            }
+            _ => Err(invalid(&format!(
+                "unsupported scheme {:?}; expected tcp or unix",
+                scheme
+            ))),
+        }
+    }
+}
+
+/// An endpoint is deserialized from its URL.
+impl<'de> Deserialize<'de> for Endpoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let url = String::deserialize(deserializer)?;
+        url.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Split `host[:port]` or `[ipv6][:port]`, ignoring a trailing `/`.
+fn parse_authority(authority: &str, default_port: u16) -> Result<(String, u16), &'static str> {
+    let authority = authority.strip_suffix('/').unwrap_or(authority);
+    let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        let (host, rest) = bracketed.split_once(']').ok_or("unclosed [ in the host")?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':').ok_or("invalid port")?)),
+        }
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() || host.contains('/') {
+        return Err("invalid host");
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| "invalid port")?,
+        None => default_port,
+    };
+    Ok((host.to_string(), port))
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp://[{}]:{}", host, port)
+            }
+            Endpoint::Tcp { host, port } => write!(f, "tcp://{}:{}", host, port),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> Endpoint {
+        Endpoint::Tcp {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let cases = [
+            ("tcp://localhost:24225", tcp("localhost", 24225)),
+            ("tcp://fluentd", tcp("fluentd", 24224)),
+            ("tcp://127.0.0.1:24224/", tcp("127.0.0.1", 24224)),
+            ("tcp://[::1]:24225", tcp("::1", 24225)),
+            ("tcp://[::1]", tcp("::1", 24224)),
+            (
+                "unix:///var/run/fluent.sock",
+                Endpoint::Unix(PathBuf::from("/var/run/fluent.sock")),
+            ),
+        ];
+        for (url, want) in cases {
+            assert_eq!(url.parse::<Endpoint>().unwrap(), want, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for url in [
+            "localhost:24224",
+            "http://localhost",
+            "tcp://",
+            "tcp://:24224",
+            "tcp://localhost:port",
+            "tcp://localhost:99999",
+            "tcp://[::1",
+            "tcp://localhost?ca=/ca.pem",
+            "tls://localhost",
+            "unix://relative.sock",
+        ] {
+            assert!(
+                matches!(url.parse::<Endpoint>(), Err(Error::InvalidConfig(_))),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        for url in [
+            "tcp://localhost:24224",
+            "tcp://[::1]:24225",
+            "unix:///var/run/fluent.sock",
+        ] {
+            assert_eq!(url.parse::<Endpoint>().unwrap().to_string(), url);
+        }
+        match "tls://fluentd:24284".parse::<Endpoint>() {
+            Err(Error::InvalidConfig(reason)) => {
+                assert!(reason.contains("unsupported scheme \"tls\""), "{}", reason)
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let addr: SocketAddr = "[::1]:24224".parse().unwrap();
+        assert_eq!(Endpoint::from(addr), tcp("::1", 24224));
+    }
+}
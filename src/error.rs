@@ -0,0 +1,198 @@
+//! Errors of the client and its worker.
+
+use std::time::Duration;
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error of the client or its worker.
+///
+/// ## Example
+///
+/// ```
+/// use tokio_fluent::{Client, Config, Error};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     match Client::new_tcp("127.0.0.1:24224".parse().unwrap(), &Config::default()).await {
+///         Ok(_client) => {}
+///         Err(Error::ConnectTimeout(timeout)) => eprintln!("timed out after {:?}", timeout),
+///         Err(Error::Io(e)) => eprintln!("failed to connect: {}", e),
+///         Err(e) => eprintln!("{}", e),
+///     }
+/// }
+/// ```
+pub enum Error {
+    /// Connecting to the server did not complete within the timeout.
+    ConnectTimeout(Duration),
+    /// An I/O error on the connection.
+    Io(std::io::Error),
+    /// Writing a record did not complete within the timeout.
+    WriteTimeout(Duration),
+    /// The ack of a record was not received within the timeout.
+    AckTimeout(Duration),
+    /// The queue of the worker is full.
+    QueueFull,
+    /// The worker has terminated, so no more records can be sent.
+    WorkerTerminated,
+    /// A record could not be serialized.
+    Serialization(rmp_serde::encode::Error),
+    /// A tag does not follow the rules of fluentd tags.
+    InvalidTag(String),
+    /// A pre-encoded record is not a well-formed MessagePack map.
+    InvalidRecord(String),
+    /// The server returned an ack which matches no record waiting for one.
+    AckMismatch { ack: String },
+    /// The server closed the connection.
+    ConnectionClosed,
+    /// All the retries failed. `last` is the error of the last attempt.
+    MaxRetriesExceeded {
+        retries: u32,
+        last: Option<Box<Error>>,
+    },
+    /// The configuration of a client is not valid.
+    InvalidConfig(String),
+    /// Closing a client did not complete within the timeout,
+    /// so the records not written yet were dropped.
+    CloseTimeout(Duration),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            Error::MaxRetriesExceeded {
+                last: Some(last), ..
+            } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Sources which cannot be cloned, such as [`std::io::Error`], are replaced with their messages.
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match self {
+            Error::ConnectTimeout(timeout) => Error::ConnectTimeout(*timeout),
+            Error::Io(e) => Error::Io(std::io::Error::new(e.kind(), e.to_string())),
+            Error::WriteTimeout(timeout) => Error::WriteTimeout(*timeout),
+            Error::AckTimeout(timeout) => Error::AckTimeout(*timeout),
+            Error::QueueFull => Error::QueueFull,
+            Error::WorkerTerminated => Error::WorkerTerminated,
+            Error::Serialization(e) => {
+                Error::Serialization(rmp_serde::encode::Error::Syntax(e.to_string()))
+            }
+            Error::AckMismatch { ack } => Error::AckMismatch { ack: ack.clone() },
+            Error::InvalidTag(tag) => Error::InvalidTag(tag.clone()),
+            Error::InvalidRecord(reason) => Error::InvalidRecord(reason.clone()),
+            Error::ConnectionClosed => Error::ConnectionClosed,
+            Error::MaxRetriesExceeded { retries, last } => Error::MaxRetriesExceeded {
+                retries: *retries,
+                last: last.clone(),
+            },
+            Error::InvalidConfig(reason) => Error::InvalidConfig(reason.clone()),
+            Error::CloseTimeout(timeout) => Error::CloseTimeout(*timeout),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ConnectTimeout(timeout) => {
+                write!(f, "connection timed out after {:?}", timeout)
+            }
+            Error::Io(_) => write!(f, "i/o error"),
+            Error::WriteTimeout(timeout) => write!(f, "write timed out after {:?}", timeout),
+            Error::AckTimeout(timeout) => write!(f, "ack timed out after {:?}", timeout),
+            Error::QueueFull => write!(f, "queue of the worker is full"),
+            Error::WorkerTerminated => write!(f, "worker has terminated"),
+            Error::Serialization(_) => write!(f, "failed to serialize a record"),
+            Error::AckMismatch { ack } => {
+                write!(
+                    f,
+                    "response ack did not match any pending chunk. ack: {}",
+                    ack
+                )
+            }
+            Error::InvalidTag(tag) => write!(f, "invalid tag: {:?}", tag),
+            Error::InvalidRecord(reason) => write!(f, "invalid record: {}", reason),
+            Error::ConnectionClosed => write!(f, "connection closed"),
+            Error::MaxRetriesExceeded { retries, .. } => {
+                write!(f, "max retries exceeded ({} retries)", retries)
+            }
+            Error::InvalidConfig(reason) => write!(f, "invalid config: {}", reason),
+            Error::CloseTimeout(timeout) => write!(f, "close timed out after {:?}", timeout),
+        }
+    }
+}
+
+/// Display an error followed by all its sources, for logging.
+pub(crate) struct Chain<'a>(pub &'a Error);
+
+impl std::fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = std::error::Error::source(self.0);
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error::Serialization(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn test_clone() {
+        let error = Error::Io(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "broken pipe",
+        ));
+        match error.clone() {
+            Error::Io(e) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe);
+                assert_eq!(e.to_string(), "broken pipe");
+            }
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_source() {
+        let error = Error::MaxRetriesExceeded {
+            retries: 3,
+            last: Some(Box::new(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "broken pipe",
+            )))),
+        };
+        assert_eq!(error.to_string(), "max retries exceeded (3 retries)");
+
+        let last = error.source().expect("no source");
+        assert_eq!(last.to_string(), "i/o error");
+        assert_eq!(last.source().expect("no source").to_string(), "broken pipe");
+        assert!(Error::QueueFull.source().is_none());
+        assert_eq!(
+            Chain(&error).to_string(),
+            "max retries exceeded (3 retries): i/o error: broken pipe"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Default fields merged into every record.
+//!
+//! ## Example
+//!
+//! ```
+//! use tokio_fluent::Config;
+//! use tokio_fluent::fields::{ConflictPolicy, DefaultFields};
+//!
+//! let config = Config {
+//!     default_fields: DefaultFields::new()
+//!         .insert("service", "billing")
+//!         .insert("env", "production")
+//!         .insert_with("thread", || {
+//!             std::thread::current().name().unwrap_or("unnamed").into()
+//!         })
+//!         .with_conflict_policy(ConflictPolicy::KeepRecord),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::record::{Map, Value};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// What to do when a record already has a key of a default field.
+pub enum ConflictPolicy {
+    /// Keep the value of the record.
+    #[default]
+    KeepRecord,
+    /// Replace the value of the record with the default field.
+    Override,
+}
+
+#[derive(Clone)]
+enum FieldValue {
+    Static(Value),
+    Computed(Arc<dyn Fn() -> Value + Send + Sync>),
+}
+
+impl Debug for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Static(value) => Debug::fmt(value, f),
+            FieldValue::Computed(_) => f.write_str("<computed>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Fields merged into every record sent with a [`Map`], such as `hostname` or `service`.
+///
+/// Records sent pre-encoded are not changed.
+pub struct DefaultFields {
+    fields: Vec<(String, FieldValue)>,
+    conflict_policy: ConflictPolicy,
+}
+
+impl DefaultFields {
+    /// Create an empty set of fields. Keys of the records are kept on conflicts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a field with a fixed value.
+    pub fn insert(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.fields
+            .push((key.into(), FieldValue::Static(value.into())));
+        self
+    }
+
+    /// Add a field whose value is computed by `f` for every record it is merged into.
+    pub fn insert_with<F>(mut self, key: impl Into<String>, f: F) -> Self
+    where
+        F: Fn() -> Value + Send + Sync + 'static,
+    {
+        self.fields
+            .push((key.into(), FieldValue::Computed(Arc::new(f))));
+        self
+    }
+
+    /// Set what to do when a record already has a key of a default field.
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Return true if there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Merge the fields into `map`.
+    pub fn merge_into(&self, map: &mut Map) {
+        for (key, value) in &self.fields {
+            if self.conflict_policy == ConflictPolicy::KeepRecord && map.contains_key(key) {
+                continue;
+            }
+            let value = match value {
+                FieldValue::Static(value) => value.clone(),
+                FieldValue::Computed(f) => f(),
+            };
+            map.insert(key.clone(), value);
+        }
+    }
+}
+
+/// Default fields are deserialized from a map of keys to string values.
+impl<'de> Deserialize<'de> for DefaultFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = BTreeMap::<String, String>::deserialize(deserializer)?;
+        Ok(fields
+            .into_iter()
+            .fold(Self::new(), |fields, (key, value)| {
+                fields.insert(key, value)
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_merge_into() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let counter = computed.clone();
+        let fields = DefaultFields::new()
+            .insert("service", "billing")
+            .insert("env", "production")
+            .insert_with("seq", move || {
+                counter.fetch_add(1, Ordering::Relaxed).into()
+            });
+
+        let mut map = Map::new();
+        map.insert("env".to_string(), "staging".into());
+        fields.merge_into(&mut map);
+        assert_eq!(map["service"], Value::from("billing"));
+        assert_eq!(map["env"], Value::from("staging"));
+        assert_eq!(map["seq"], Value::from(0usize));
+
+        let fields = fields.with_conflict_policy(ConflictPolicy::Override);
+        fields.merge_into(&mut map);
+        assert_eq!(map["env"], Value::from("production"));
+        assert_eq!(map["seq"], Value::from(1usize));
+        assert_eq!(computed.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_computed_values_are_not_evaluated_on_conflict() {
+        let fields = DefaultFields::new().insert_with("key", || unreachable!());
+        let mut map = Map::new();
+        map.insert("key".to_string(), 1.into());
+        fields.merge_into(&mut map);
+        assert_eq!(map["key"], Value::from(1));
+        assert!(!fields.is_empty());
+        assert!(DefaultFields::new().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+//! Filters applied to records before they are queued.
+//!
+//! A [`Filter`] can change the tag, timestamp and fields of a record, or drop it.
+//! The filters of a [`FilterChain`] are applied in order, and a dropped record is not
+//! passed to the following filters.
+//!
+//! ## Example
+//!
+//! ```
+//! use tokio_fluent::Config;
+//! use tokio_fluent::filter::{Decision, FilterChain, Redact, Rename};
+//! use tokio_fluent::record::Map;
+//!
+//! let config = Config {
+//!     filters: FilterChain::new()
+//!         .with(Redact::new(["password", "token"]))
+//!         .with(Rename::new("msg", "message"))
+//!         .with(|tag: &mut String, _: &mut i64, record: &mut Map| {
+//!             if tag.starts_with("debug.") && !record.contains_key("important") {
+//!                 return Decision::Drop;
+//!             }
+//!             Decision::Keep
+//!         }),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::record::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether a record is kept after a filter.
+pub enum Decision {
+    /// Pass the record to the next filter, or queue it after the last one.
+    Keep,
+    /// Drop the record.
+    Drop,
+}
+
+/// A filter of records.
+///
+/// It is implemented for closures taking the tag, timestamp and fields of a record.
+pub trait Filter: Send + Sync {
+    /// Change the record in place, and return whether to keep it.
+    fn filter(&self, tag: &mut String, timestamp: &mut i64, record: &mut Map) -> Decision;
+}
+
+impl<F> Filter for F
+where
+    F: Fn(&mut String, &mut i64, &mut Map) -> Decision + Send + Sync,
+{
+    fn filter(&self, tag: &mut String, timestamp: &mut i64, record: &mut Map) -> Decision {
+        self(tag, timestamp, record)
+    }
+}
+
+impl Debug for dyn Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Filter")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// An ordered list of filters.
+pub struct FilterChain {
+    filters: Vec<Arc<dyn Filter>>,
+}
+
+impl FilterChain {
+    /// Create an empty chain, which keeps every record as it is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a filter to the chain.
+    pub fn with<F: Filter + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Return true if there are no filters.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Apply the filters in order, stopping at the first one which drops the record.
+    pub fn apply(&self, tag: &mut String, timestamp: &mut i64, record: &mut Map) -> Decision {
+        for filter in &self.filters {
+            if filter.filter(tag, timestamp, record) == Decision::Drop {
+                return Decision::Drop;
+            }
+        }
+        Decision::Keep
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Replace the values of the given keys, e.g. to hide personal information.
+pub struct Redact {
+    keys: Vec<String>,
+    replacement: Value,
+}
+
+impl Redact {
+    /// Create a filter which replaces the values of `keys` with `"[REDACTED]"`.
+    pub fn new<I, K>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        Self {
+            keys: keys.into_iter().map(Into::into).collect(),
+            replacement: "[REDACTED]".into(),
+        }
+    }
+
+    /// Set the value which replaces the redacted values.
+    pub fn with_replacement(mut self, replacement: impl Into<Value>) -> Self {
+        self.replacement = replacement.into();
+        self
+    }
+}
+
+impl Filter for Redact {
+    fn filter(&self, _tag: &mut String, _timestamp: &mut i64, record: &mut Map) -> Decision {
+        for key in &self.keys {
+            if let Some(value) = record.get_mut(key) {
+                *value = self.replacement.clone();
+            }
+        }
+        Decision::Keep
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Rename a key of records. An existing value of the new key is replaced.
+pub struct Rename {
+    from: String,
+    to: String,
+}
+
+impl Rename {
+    /// Create a filter which renames the key `from` to `to`.
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+impl Filter for Rename {
+    fn filter(&self, _tag: &mut String, _timestamp: &mut i64, record: &mut Map) -> Decision {
+        if let Some(value) = record.remove(&self.from) {
+            record.insert(self.to.clone(), value);
+        }
+        Decision::Keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_filter_chain() {
+        let called = Arc::new(AtomicUsize::new(0));
+        let counter = called.clone();
+        let chain = FilterChain::new()
+            .with(Redact::new(["password"]))
+            .with(Rename::new("msg", "message"))
+            .with(|tag: &mut String, timestamp: &mut i64, _: &mut Map| {
+                if tag == "debug" {
+                    return Decision::Drop;
+                }
+                tag.push_str(".filtered");
+                *timestamp += 1;
+                Decision::Keep
+            })
+            .with(move |_: &mut String, _: &mut i64, _: &mut Map| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Decision::Keep
+            });
+
+        let (mut tag, mut timestamp) = ("app".to_string(), 0);
+        let mut record = Map::new();
+        record.insert("password".to_string(), "secret".into());
+        record.insert("msg".to_string(), "hello".into());
+        assert_eq!(
+            chain.apply(&mut tag, &mut timestamp, &mut record),
+            Decision::Keep
+        );
+        assert_eq!((tag.as_str(), timestamp), ("app.filtered", 1));
+        assert_eq!(record["password"], "[REDACTED]".into());
+        assert_eq!(record["message"], "hello".into());
+        assert!(!record.contains_key("msg"));
+
+        let mut tag = "debug".to_string();
+        assert_eq!(
+            chain.apply(&mut tag, &mut timestamp, &mut record),
+            Decision::Drop
+        );
+        assert_eq!(called.load(Ordering::Relaxed), 1);
+    }
+}
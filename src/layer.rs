@@ -0,0 +1,217 @@
+//! [tracing-subscriber](https://docs.rs/tracing-subscriber) layer which ships events to fluentd.
+//!
+//! This module is available with the `tracing` feature.
+//!
+//! ## Example
+//!
+//! ```
+//! use tokio_fluent::{Client, Config};
+//! use tokio_fluent::layer::FluentLayer;
+//! use tracing_subscriber::prelude::*;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = Client::new_tcp(
+//!         "127.0.0.1:24224".parse().unwrap(),
+//!         &Config{..Default::default()},
+//!     )
+//!     .await
+//!     .unwrap();
+//!
+//!     let layer = FluentLayer::new(client, |meta| format!("app.{}", meta.level().as_str()));
+//!     tracing_subscriber::registry().with(layer).init();
+//! }
+//! ```
+
+use std::fmt::Debug;
+
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::client::FluentClient;
+use crate::logger::is_crate_target;
+use crate::record::{Map, Value};
+
+/// A `tracing_subscriber::Layer` that sends every event through a [`FluentClient`].
+///
+/// Each event becomes a fluent record which contains the fields of the event,
+/// `level`, `target` and `spans`. `spans` is the list of the spans the event
+/// belongs to, from the root, each with its `name` and its fields.
+///
+/// The tag of a record is computed from the metadata of the event by `tag_fn`.
+/// Events whose tags are rejected by the [`TagPolicy`](crate::tag::TagPolicy) of the client are dropped,
+/// so a tag built from a target such as `my_crate::module` should replace its `::`.
+pub struct FluentLayer<C, F> {
+    client: C,
+    tag_fn: F,
+}
+
+impl<C, F> FluentLayer<C, F>
+where
+    C: FluentClient + 'static,
+    F: Fn(&Metadata<'_>) -> String + Send + Sync + 'static,
+{
+    /// Create a layer which sends events through `client` with the tag returned by `tag_fn`.
+    pub fn new(client: C, tag_fn: F) -> Self {
+        Self { client, tag_fn }
+    }
+}
+
+/// Fields of a span, stored in the extensions of the span.
+struct SpanFields(Map);
+
+struct FieldVisitor<'a>(&'a mut Map);
+
+impl FieldVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+impl<S, C, F> Layer<S> for FluentLayer<C, F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    C: FluentClient + 'static,
+    F: Fn(&Metadata<'_>) -> String + Send + Sync + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut FieldVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if is_crate_target(metadata.target()) {
+            return;
+        }
+
+        let mut map = Map::new();
+        event.record(&mut FieldVisitor(&mut map));
+        map.insert("level".to_string(), metadata.level().as_str().into());
+        map.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope(event) {
+            let spans = scope
+                .from_root()
+                .map(|span| {
+                    let mut fields = span
+                        .extensions()
+                        .get::<SpanFields>()
+                        .map(|SpanFields(fields)| fields.clone())
+                        .unwrap_or_default();
+                    fields.insert("name".to_string(), span.name().into());
+                    fields.into()
+                })
+                .collect::<Vec<Value>>();
+            map.insert("spans".to_string(), spans.into());
+        }
+
+        let _ = self.client.send(&(self.tag_fn)(metadata), map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+    use crate::client::MemoryClient;
+
+    #[test]
+    fn test_on_event() {
+        let client = MemoryClient::new();
+        let layer = FluentLayer::new(client.clone(), |meta| {
+            format!("app.{}", meta.level().as_str().to_lowercase())
+        });
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", id = 7, path = tracing::field::Empty);
+            let _enter = span.enter();
+            span.record("path", "/users");
+            tracing::warn!(target: "app::server", age = 22, ok = true, "hello {}", "world");
+        });
+
+        let got = client.records();
+        assert_eq!(got.len(), 1);
+        let (tag, map) = (&got[0].tag, &got[0].record);
+        assert_eq!(tag, "app.warn");
+        assert_eq!(map["message"], Value::from("hello world"));
+        assert_eq!(map["age"], Value::from(22));
+        assert_eq!(map["ok"], Value::from(true));
+        assert_eq!(map["level"], Value::from("WARN"));
+        assert_eq!(map["target"], Value::from("app::server"));
+
+        let mut span = Map::new();
+        span.insert("name".to_string(), "request".into());
+        span.insert("id".to_string(), 7.into());
+        span.insert("path".to_string(), "/users".into());
+        assert_eq!(map["spans"], Value::from(vec![span.into()]));
+    }
+
+    #[test]
+    fn test_on_event_without_span() {
+        let client = MemoryClient::new();
+        let layer = FluentLayer::new(client.clone(), |meta| meta.target().to_string());
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "app", "started");
+            tracing::warn!(target: "tokio_fluent::worker", "failed to write");
+        });
+
+        let got = client.records();
+        assert_eq!(got.len(), 1);
+        let (tag, map) = (&got[0].tag, &got[0].record);
+        assert_eq!(tag, "app");
+        assert!(!map.contains_key("spans"));
+    }
+}
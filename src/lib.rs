@@ -31,8 +31,27 @@
 //! }
 //! ```
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod builder;
 pub mod client;
+mod config;
+pub mod endpoint;
+pub mod error;
+pub mod fields;
+pub mod filter;
+#[cfg(feature = "tracing")]
+pub mod layer;
+pub mod logger;
 pub mod record;
+pub mod retry;
+pub mod sampling;
+pub mod stats;
+pub mod tag;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod worker;
 
-pub use client::{Client, Config, FluentClient};
+pub use builder::ClientBuilder;
+pub use client::{AsyncFluentClient, Client, Config, FluentClient};
+pub use error::Error;
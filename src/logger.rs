@@ -0,0 +1,240 @@
+//! [log](https://docs.rs/log) backend which forwards log records to fluentd.
+//!
+//! ## Example
+//!
+//! ```
+//! use tokio_fluent::{Client, Config};
+//! use tokio_fluent::logger::FluentLogger;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = Client::new_tcp(
+//!         "127.0.0.1:24224".parse().unwrap(),
+//!         &Config{..Default::default()},
+//!     )
+//!     .await
+//!     .unwrap();
+//!
+//!     FluentLogger::new(client, "app.log").init().unwrap();
+//!     log::info!("hello from {}", "tokio-fluent");
+//! }
+//! ```
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::client::FluentClient;
+use crate::record::Map;
+
+/// Log records emitted by this crate itself are never forwarded,
+/// otherwise a failing worker would keep logging about its own failures.
+const CRATE_TARGET: &str = "tokio_fluent";
+
+/// Return true if `target` is this crate or one of its modules,
+/// but not another crate whose name starts with `tokio_fluent`.
+pub(crate) fn is_crate_target(target: &str) -> bool {
+    target == CRATE_TARGET
+        || target
+            .strip_prefix(CRATE_TARGET)
+            .is_some_and(|rest| rest.starts_with("::"))
+}
+
+/// A `log::Log` implementation that sends every log record through a [`FluentClient`].
+///
+/// Each log record becomes a fluent record with `level`, `target`, `module_path`,
+/// `file`, `line` and `message` fields. When the `kv` feature is enabled,
+/// the structured key-values of the log record are included as well.
+pub struct FluentLogger<C: FluentClient> {
+    client: C,
+    tag: String,
+    level: LevelFilter,
+}
+
+impl<C: FluentClient> FluentLogger<C> {
+    /// Create a logger which sends log records with `tag`.
+    /// All levels are enabled by default.
+    pub fn new(client: C, tag: &str) -> Self {
+        Self {
+            client,
+            tag: tag.to_string(),
+            level: LevelFilter::Trace,
+        }
+    }
+
+    /// Set the maximum level of log records to send.
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Install this logger as the global logger of the `log` crate.
+    pub fn init(self) -> Result<(), SetLoggerError>
+    where
+        C: 'static,
+    {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn to_map(record: &Record) -> Map {
+        let mut map = Map::new();
+        #[cfg(feature = "kv")]
+        {
+            let mut visitor = KeyValues(&mut map);
+            let _ = record.key_values().visit(&mut visitor);
+        }
+        map.insert("level".to_string(), record.level().as_str().into());
+        map.insert("target".to_string(), record.target().into());
+        if let Some(module_path) = record.module_path() {
+            map.insert("module_path".to_string(), module_path.into());
+        }
+        if let Some(file) = record.file() {
+            map.insert("file".to_string(), file.into());
+        }
+        if let Some(line) = record.line() {
+            map.insert("line".to_string(), line.into());
+        }
+        map.insert("message".to_string(), record.args().to_string().into());
+        map
+    }
+}
+
+impl<C: FluentClient> Log for FluentLogger<C> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && !is_crate_target(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let _ = self.client.send(&self.tag, Self::to_map(record));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "kv")]
+struct KeyValues<'a>(&'a mut Map);
+
+#[cfg(feature = "kv")]
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(v) = value.to_bool() {
+            v.into()
+        } else if let Some(v) = value.to_i64() {
+            v.into()
+        } else if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_f64() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+    use crate::client::MemoryClient;
+    use crate::record::Value;
+
+    #[test]
+    fn test_log() {
+        let client = MemoryClient::new();
+        let logger = FluentLogger::new(client.clone(), "app.log");
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello {}", 42))
+                .level(Level::Warn)
+                .target("app::server")
+                .module_path(Some("app::server"))
+                .file(Some("src/server.rs"))
+                .line(Some(10))
+                .build(),
+        );
+
+        let got = client.records();
+        assert_eq!(got.len(), 1);
+        let (tag, map) = (&got[0].tag, &got[0].record);
+        assert_eq!(tag, "app.log");
+        assert_eq!(map["level"], Value::from("WARN"));
+        assert_eq!(map["target"], Value::from("app::server"));
+        assert_eq!(map["module_path"], Value::from("app::server"));
+        assert_eq!(map["file"], Value::from("src/server.rs"));
+        assert_eq!(map["line"], Value::from(10u32));
+        assert_eq!(map["message"], Value::from("hello 42"));
+    }
+
+    #[test]
+    fn test_log_filters_level_and_own_target() {
+        let client = MemoryClient::new();
+        let logger = FluentLogger::new(client.clone(), "app.log").with_level(LevelFilter::Info);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("debug"))
+                .level(Level::Debug)
+                .target("app")
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("failed to write"))
+                .level(Level::Warn)
+                .target("tokio_fluent::worker")
+                .build(),
+        );
+        assert!(client.is_empty());
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("extended"))
+                .level(Level::Info)
+                .target("tokio_fluent_ext")
+                .build(),
+        );
+        assert_eq!(client.len(), 1);
+    }
+
+    #[test]
+    fn test_is_crate_target() {
+        assert!(is_crate_target("tokio_fluent"));
+        assert!(is_crate_target("tokio_fluent::worker"));
+        assert!(!is_crate_target("tokio_fluent_ext"));
+        assert!(!is_crate_target("tokio_fluent_ext::module"));
+        assert!(!is_crate_target("app"));
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_log_key_values() {
+        let client = MemoryClient::new();
+        let logger = FluentLogger::new(client.clone(), "app.log");
+
+        let kvs = [("user", log::kv::Value::from("john")), ("age", 22.into())];
+        logger.log(
+            &Record::builder()
+                .args(format_args!("login"))
+                .level(Level::Info)
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let got = client.records();
+        let map = &got[0].record;
+        assert_eq!(map["user"], Value::from("john"));
+        assert_eq!(map["age"], Value::from(22));
+    }
+}
@@ -3,9 +3,13 @@
 use core::fmt::Debug;
 use std::collections::HashMap;
 
+use bytes::{BufMut, Bytes, BytesMut};
+use rmp::Marker;
 use serde::ser::{SerializeMap, SerializeSeq};
 use serde::{Serialize, Serializer};
 
+use crate::error::Error;
+
 #[derive(Clone, PartialEq)]
 /// HashMap object for fluent record.
 pub struct Map(HashMap<String, Value>);
@@ -24,6 +28,140 @@ impl Map {
         }
         Self(m)
     }
+
+    /// Encode the map as MessagePack, so it can be sent by
+    /// [`Client::send_encoded`](crate::Client::send_encoded) without being encoded again.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tokio_fluent::record::Map;
+    ///
+    /// let mut map = Map::new();
+    /// map.insert("age".to_string(), 22.into());
+    /// assert_eq!(map.encode().unwrap().as_bytes(), b"\x81\xa3age\x16");
+    /// ```
+    pub fn encode(&self) -> Result<EncodedMap, Error> {
+        let mut writer = BytesMut::new().writer();
+        self.serialize(&mut rmp_serde::Serializer::new(&mut writer))?;
+        Ok(EncodedMap(writer.into_inner().freeze()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A record body encoded as a MessagePack map in advance.
+pub struct EncodedMap(Bytes);
+
+impl EncodedMap {
+    /// Wrap a record body which is already encoded as a MessagePack map.
+    ///
+    /// The bytes are checked to be exactly one well-formed map with string keys,
+    /// without decoding the values. [`Error::InvalidRecord`] is returned otherwise.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bytes::Bytes;
+    /// use tokio_fluent::record::EncodedMap;
+    ///
+    /// assert!(EncodedMap::from_bytes(Bytes::from_static(b"\x81\xa3age\x16")).is_ok());
+    /// assert!(EncodedMap::from_bytes(Bytes::from_static(b"\x81\xa3age")).is_err());
+    /// ```
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, Error> {
+        validate_map(&bytes).map_err(|reason| Error::InvalidRecord(reason.to_string()))?;
+        Ok(Self(bytes))
+    }
+
+    /// The encoded bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Convert into the encoded bytes.
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+/// The maximum nesting of arrays and maps accepted by [`EncodedMap::from_bytes`].
+const MAX_DEPTH: usize = 256;
+
+fn validate_map(mut buf: &[u8]) -> Result<(), &'static str> {
+    let len = match buf.first().copied().map(Marker::from_u8) {
+        Some(Marker::FixMap(_) | Marker::Map16 | Marker::Map32) => {
+            rmp::decode::read_map_len(&mut buf).map_err(|_| "truncated")?
+        }
+        Some(_) => return Err("not a map"),
+        None => return Err("empty"),
+    };
+    for _ in 0..len {
+        match buf.first().copied().map(Marker::from_u8) {
+            Some(Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32) => {}
+            Some(_) => return Err("key is not a string"),
+            None => return Err("truncated"),
+        }
+        skip_value(&mut buf, 1)?;
+        skip_value(&mut buf, 1)?;
+    }
+    if !buf.is_empty() {
+        return Err("trailing bytes");
+    }
+    Ok(())
+}
+
+/// Skip one MessagePack value at the head of `buf`, checking only its structure.
+fn skip_value(buf: &mut &[u8], depth: usize) -> Result<(), &'static str> {
+    if depth > MAX_DEPTH {
+        return Err("too deeply nested");
+    }
+    let (&first, rest) = buf.split_first().ok_or("truncated")?;
+    *buf = rest;
+    let (len, values) = match Marker::from_u8(first) {
+        Marker::FixPos(_) | Marker::FixNeg(_) | Marker::Null | Marker::True | Marker::False => {
+            (0, 0)
+        }
+        Marker::U8 | Marker::I8 => (1, 0),
+        Marker::U16 | Marker::I16 => (2, 0),
+        Marker::U32 | Marker::I32 | Marker::F32 => (4, 0),
+        Marker::U64 | Marker::I64 | Marker::F64 => (8, 0),
+        Marker::FixStr(n) => (n as usize, 0),
+        Marker::Str8 | Marker::Bin8 => (read_len(buf, 1)?, 0),
+        Marker::Str16 | Marker::Bin16 => (read_len(buf, 2)?, 0),
+        Marker::Str32 | Marker::Bin32 => (read_len(buf, 4)?, 0),
+        Marker::FixArray(n) => (0, n as usize),
+        Marker::Array16 => (0, read_len(buf, 2)?),
+        Marker::Array32 => (0, read_len(buf, 4)?),
+        Marker::FixMap(n) => (0, n as usize * 2),
+        Marker::Map16 => (0, read_len(buf, 2)? * 2),
+        Marker::Map32 => (0, read_len(buf, 4)? * 2),
+        Marker::FixExt1 => (2, 0),
+        Marker::FixExt2 => (3, 0),
+        Marker::FixExt4 => (5, 0),
+        Marker::FixExt8 => (9, 0),
+        Marker::FixExt16 => (17, 0),
+        Marker::Ext8 => (read_len(buf, 1)? + 1, 0),
+        Marker::Ext16 => (read_len(buf, 2)? + 1, 0),
+        Marker::Ext32 => (read_len(buf, 4)? + 1, 0),
+        Marker::Reserved => return Err("reserved marker"),
+    };
+    if buf.len() < len {
+        return Err("truncated");
+    }
+    *buf = &buf[len..];
+    for _ in 0..values {
+        skip_value(buf, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Read a big-endian length of `size` bytes.
+fn read_len(buf: &mut &[u8], size: usize) -> Result<usize, &'static str> {
+    if buf.len() < size {
+        return Err("truncated");
+    }
+    let (len, rest) = buf.split_at(size);
+    *buf = rest;
+    Ok(len.iter().fold(0, |n, b| n << 8 | *b as usize))
 }
 
 #[macro_export]
@@ -263,4 +401,39 @@ mod tests {
         );
         assert_eq!(got, want);
     }
+
+    #[test]
+    fn test_encoded_map_from_bytes() {
+        let map = record_map!(
+            "name".to_string() => "John".into(),
+            "scores".to_string() => [70, 80].into_iter().map(|e| e.into()).collect::<Vec<_>>().into(),
+            "nested".to_string() => record_map!("ratio".to_string() => 0.5.into()).into(),
+        );
+        let encoded = map.encode().unwrap();
+        assert_eq!(
+            EncodedMap::from_bytes(encoded.clone().into_bytes()).unwrap(),
+            encoded
+        );
+        assert!(EncodedMap::from_bytes(Bytes::from_static(b"\x80")).is_ok());
+
+        let bytes = encoded.into_bytes();
+        for invalid in [
+            Bytes::new(),
+            bytes.slice(..bytes.len() - 1),
+            [&bytes[..], b"\xc0"].concat().into(),
+            Bytes::from_static(b"\x92\x01\x02"),
+            Bytes::from_static(b"\x81\x01\x02"),
+            Bytes::from_static(b"\x81\xa1a\xc1"),
+            Bytes::from_static(b"\x81\xa1a\xdd\xff\xff\xff\xff"),
+        ] {
+            assert!(
+                matches!(
+                    EncodedMap::from_bytes(invalid.clone()),
+                    Err(Error::InvalidRecord(_))
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+    }
 }
@@ -0,0 +1,350 @@
+//! Retry policies of the worker.
+//!
+//! When writing a record fails, the worker asks the [`RetryPolicy`] of
+//! [`Config`](crate::Config) how long to wait before the next attempt, or whether to give up.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! use tokio_fluent::Config;
+//! use tokio_fluent::retry::ExponentialBackoff;
+//!
+//! let config = Config {
+//!     retry_policy: Arc::new(
+//!         ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(10)).forever(),
+//!     ),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// A strategy deciding how long to wait between attempts to write a record.
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// Return the wait before the next attempt, or `None` to give up the record.
+    ///
+    /// `attempt` is the number of failed attempts so far, starting from 1.
+    /// `last` is the wait returned for the previous attempt, or zero for the first retry.
+    fn next_wait(&self, attempt: u32, last: Duration) -> Option<Duration>;
+
+    /// Check the parameters of the policy, returning [`Error::InvalidConfig`] if they are not valid.
+    /// It is called before a client connects. The default accepts any parameters.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn exhausted(max_retries: Option<u32>, attempt: u32) -> bool {
+    max_retries.is_some_and(|max| attempt > max)
+}
+
+/// A random duration between `low` and `high`, both inclusive.
+fn random_between(low: Duration, high: Duration) -> Duration {
+    if high <= low {
+        return low;
+    }
+    Duration::from_nanos(fastrand::u64(
+        low.as_nanos() as u64..=high.as_nanos() as u64,
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Exponential backoff, with full jitter by default.
+///
+/// The wait before the N-th retry is `initial * multiplier^(N-1)`, capped by `max_wait`.
+/// With jitter, the actual wait is a random duration between zero and that value.
+///
+/// When deserialized, e.g. as the `retry_policy` of [`Config`](crate::Config),
+/// the fields not given keep their defaults, durations are written like `"500ms"`,
+/// and `max_retries = "forever"` never gives up.
+pub struct ExponentialBackoff {
+    #[serde(deserialize_with = "crate::config::deserialize_duration")]
+    initial: Duration,
+    #[serde(deserialize_with = "crate::config::deserialize_duration")]
+    max_wait: Duration,
+    multiplier: f64,
+    #[serde(deserialize_with = "crate::config::deserialize_max_retries")]
+    max_retries: Option<u32>,
+    jitter: bool,
+}
+
+impl ExponentialBackoff {
+    /// Create a policy which waits `initial` before the first retry and at most `max_wait`.
+    /// The multiplier is 1.5 and the maximum number of retries is 10.
+    pub fn new(initial: Duration, max_wait: Duration) -> Self {
+        Self {
+            initial,
+            max_wait,
+            multiplier: 1.5,
+            max_retries: Some(10),
+            jitter: true,
+        }
+    }
+
+    /// Set the factor the wait is multiplied by after every retry.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the maximum number of retries.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Retry forever.
+    pub fn forever(mut self) -> Self {
+        self.max_retries = None;
+        self
+    }
+
+    /// Wait exactly the computed duration instead of a random duration up to it.
+    pub fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    /// The duration of the wait before the first retry.
+    pub fn initial(&self) -> Duration {
+        self.initial
+    }
+
+    /// The maximum duration of a wait.
+    pub fn max_wait(&self) -> Duration {
+        self.max_wait
+    }
+
+    /// The maximum number of retries, or `None` when retrying forever.
+    pub fn max_retries(&self) -> Option<u32> {
+        self.max_retries
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(60))
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn next_wait(&self, attempt: u32, _last: Duration) -> Option<Duration> {
+        if exhausted(self.max_retries, attempt) {
+            return None;
+        }
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let wait = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+        let wait = if wait.is_finite() && wait < self.max_wait.as_secs_f64() {
+            Duration::from_secs_f64(wait)
+        } else {
+            self.max_wait
+        };
+        if self.jitter {
+            Some(random_between(Duration::ZERO, wait))
+        } else {
+            Some(wait)
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.max_wait < self.initial {
+            return Err(Error::InvalidConfig(format!(
+                "max_wait ({:?}) of the retry policy is shorter than initial ({:?})",
+                self.max_wait, self.initial
+            )));
+        }
+        if !(self.multiplier.is_finite() && self.multiplier >= 1.0) {
+            return Err(Error::InvalidConfig(format!(
+                "multiplier ({}) of the retry policy is less than 1",
+                self.multiplier
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Wait the same duration before every retry.
+pub struct ConstantBackoff {
+    wait: Duration,
+    max_retries: Option<u32>,
+}
+
+impl ConstantBackoff {
+    /// Create a policy which waits `wait` before every retry.
+    /// The maximum number of retries is 10.
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            wait,
+            max_retries: Some(10),
+        }
+    }
+
+    /// Set the maximum number of retries.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Retry forever.
+    pub fn forever(mut self) -> Self {
+        self.max_retries = None;
+        self
+    }
+}
+
+impl RetryPolicy for ConstantBackoff {
+    fn next_wait(&self, attempt: u32, _last: Duration) -> Option<Duration> {
+        if exhausted(self.max_retries, attempt) {
+            return None;
+        }
+        Some(self.wait)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Decorrelated jitter.
+///
+/// The wait is a random duration between `base` and three times the previous wait,
+/// capped by `max_wait`.
+pub struct DecorrelatedJitter {
+    base: Duration,
+    max_wait: Duration,
+    max_retries: Option<u32>,
+}
+
+impl DecorrelatedJitter {
+    /// Create a policy which waits at least `base` and at most `max_wait`.
+    /// The maximum number of retries is 10.
+    pub fn new(base: Duration, max_wait: Duration) -> Self {
+        Self {
+            base,
+            max_wait,
+            max_retries: Some(10),
+        }
+    }
+
+    /// Set the maximum number of retries.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Retry forever.
+    pub fn forever(mut self) -> Self {
+        self.max_retries = None;
+        self
+    }
+}
+
+impl RetryPolicy for DecorrelatedJitter {
+    fn next_wait(&self, attempt: u32, last: Duration) -> Option<Duration> {
+        if exhausted(self.max_retries, attempt) {
+            return None;
+        }
+        let high = last.max(self.base).saturating_mul(3);
+        Some(random_between(self.base, high).min(self.max_wait))
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.max_wait < self.base {
+            return Err(Error::InvalidConfig(format!(
+                "max_wait ({:?}) of the retry policy is shorter than base ({:?})",
+                self.max_wait, self.base
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = ExponentialBackoff::new(100 * MS, 400 * MS)
+            .with_multiplier(2.0)
+            .with_max_retries(4)
+            .without_jitter();
+        assert_eq!(policy.next_wait(1, Duration::ZERO), Some(100 * MS));
+        assert_eq!(policy.next_wait(2, 100 * MS), Some(200 * MS));
+        assert_eq!(policy.next_wait(3, 200 * MS), Some(400 * MS));
+        assert_eq!(policy.next_wait(4, 400 * MS), Some(400 * MS));
+        assert_eq!(policy.next_wait(5, 400 * MS), None);
+
+        let policy = policy.forever();
+        assert_eq!(policy.next_wait(10_000, 400 * MS), Some(400 * MS));
+    }
+
+    #[test]
+    fn test_exponential_backoff_full_jitter() {
+        let policy = ExponentialBackoff::new(100 * MS, 400 * MS);
+        for attempt in 1..=10 {
+            let cap = (100 * MS)
+                .mul_f64(1.5f64.powi(attempt as i32 - 1))
+                .min(400 * MS);
+            let wait = policy.next_wait(attempt, Duration::ZERO).unwrap();
+            assert!(wait <= cap, "attempt {}: {:?} > {:?}", attempt, wait, cap);
+        }
+        assert_eq!(policy.next_wait(11, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_constant_backoff() {
+        let policy = ConstantBackoff::new(50 * MS).with_max_retries(2);
+        assert_eq!(policy.next_wait(1, Duration::ZERO), Some(50 * MS));
+        assert_eq!(policy.next_wait(2, 50 * MS), Some(50 * MS));
+        assert_eq!(policy.next_wait(3, 50 * MS), None);
+        assert_eq!(policy.forever().next_wait(u32::MAX, 50 * MS), Some(50 * MS));
+    }
+
+    #[test]
+    fn test_decorrelated_jitter() {
+        let policy = DecorrelatedJitter::new(100 * MS, 1000 * MS).with_max_retries(20);
+        let mut last = Duration::ZERO;
+        for attempt in 1..=20 {
+            let wait = policy.next_wait(attempt, last).unwrap();
+            assert!(wait >= 100 * MS && wait <= 1000 * MS, "{:?}", wait);
+            assert!(wait <= last.max(100 * MS) * 3);
+            last = wait;
+        }
+        assert_eq!(policy.next_wait(21, last), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ExponentialBackoff::default().validate().is_ok());
+        assert!(ConstantBackoff::new(50 * MS).validate().is_ok());
+        assert!(DecorrelatedJitter::new(100 * MS, 100 * MS)
+            .validate()
+            .is_ok());
+
+        for policy in [
+            ExponentialBackoff::new(200 * MS, 100 * MS),
+            ExponentialBackoff::default().with_multiplier(0.5),
+            ExponentialBackoff::default().with_multiplier(f64::NAN),
+        ] {
+            assert!(
+                matches!(policy.validate(), Err(Error::InvalidConfig(_))),
+                "{:?}",
+                policy
+            );
+        }
+        assert!(matches!(
+            DecorrelatedJitter::new(200 * MS, 100 * MS).validate(),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+//! Sampling and rate limiting of records by tag.
+//!
+//! [`Sampling`] is a list of rules, each of which pairs a tag pattern with a [`Sampler`].
+//! The sampler of the first rule matching the tag of a record decides whether to keep it.
+//! Records are sampled before anything else is done with them,
+//! so dropping a record costs almost nothing.
+//!
+//! Tag patterns follow fluentd: `*` matches one part of a tag and `**` matches zero or more parts,
+//! e.g. `debug.**` matches `debug`, `debug.sql` and `debug.sql.slow`.
+//!
+//! ## Example
+//!
+//! ```
+//! use tokio_fluent::Config;
+//! use tokio_fluent::sampling::{OneInN, Probabilistic, RateLimit, Sampling};
+//!
+//! let config = Config {
+//!     sampling: Sampling::new()
+//!         .rule("debug.**", Probabilistic::new(0.01))
+//!         .rule("app.access", OneInN::new(10))
+//!         .rule("**", RateLimit::new(1000.0, 2000).per_tag()),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::error::Error;
+
+/// A strategy deciding whether to keep a record.
+pub trait Sampler: Debug + Send + Sync {
+    /// Return true to keep a record of `tag`.
+    fn sample(&self, tag: &str) -> bool;
+
+    /// Check the parameters of the sampler, returning [`Error::InvalidConfig`] if they are not usable.
+    /// It is called when a client is created.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Keep each record with a fixed probability.
+pub struct Probabilistic {
+    rate: f64,
+}
+
+impl Probabilistic {
+    /// Create a sampler which keeps records with the probability `rate`, between 0 and 1.
+    pub fn new(rate: f64) -> Self {
+        Self { rate }
+    }
+}
+
+impl Sampler for Probabilistic {
+    fn sample(&self, _tag: &str) -> bool {
+        self.rate >= 1.0 || fastrand::f64() < self.rate
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&self.rate) {
+            return Err(Error::InvalidConfig(format!(
+                "rate of Probabilistic must be between 0 and 1, got {}",
+                self.rate
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// Keep one of every `n` records, starting from the first one.
+pub struct OneInN {
+    n: u64,
+    count: AtomicU64,
+}
+
+impl OneInN {
+    /// Create a sampler which keeps one of every `n` records.
+    /// A zero `n` is rejected when a client is created.
+    pub fn new(n: u64) -> Self {
+        Self {
+            n,
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Sampler for OneInN {
+    fn sample(&self, _tag: &str) -> bool {
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        count.checked_rem(self.n) == Some(0)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.n == 0 {
+            return Err(Error::InvalidConfig(
+                "n of OneInN must not be zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+/// Limit the rate of records with a token bucket.
+///
+/// By default, all the tags matching the rule share one bucket.
+pub struct RateLimit {
+    per_second: f64,
+    burst: u32,
+    per_tag: bool,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimit {
+    /// Create a sampler which keeps `per_second` records a second on average,
+    /// and up to `burst` records at once.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst,
+            per_tag: false,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Use a bucket for each tag instead of one for all the tags.
+    /// A bucket is kept for every tag seen, so this is meant for tags of low cardinality.
+    pub fn per_tag(mut self) -> Self {
+        self.per_tag = true;
+        self
+    }
+}
+
+impl Sampler for RateLimit {
+    fn sample(&self, tag: &str) -> bool {
+        let key = if self.per_tag { tag } else { "" };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = match buckets.get_mut(key) {
+            Some(bucket) => bucket,
+            None => buckets.entry(key.to_string()).or_insert(Bucket {
+                tokens: self.burst as f64,
+                updated: now,
+            }),
+        };
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst as f64);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(self.per_second.is_finite() && self.per_second > 0.0) {
+            return Err(Error::InvalidConfig(format!(
+                "per_second of RateLimit must be a positive number, got {}",
+                self.per_second
+            )));
+        }
+        if self.burst == 0 {
+            return Err(Error::InvalidConfig(
+                "burst of RateLimit must not be zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A fluentd tag pattern.
+struct TagPattern {
+    parts: Vec<String>,
+}
+
+impl TagPattern {
+    fn new(pattern: &str) -> Self {
+        Self {
+            parts: pattern.split('.').map(str::to_string).collect(),
+        }
+    }
+
+    fn matches(&self, tag: &str) -> bool {
+        Self::matches_parts(&self.parts, Some(tag))
+    }
+
+    /// Match the parts of a pattern against the remaining parts of a tag,
+    /// which is None when no part is left.
+    fn matches_parts(pattern: &[String], tag: Option<&str>) -> bool {
+        let Some((first, rest)) = pattern.split_first() else {
+            return tag.is_none();
+        };
+        if first == "**" {
+            let mut tag = tag;
+            loop {
+                if Self::matches_parts(rest, tag) {
+                    return true;
+                }
+                match tag {
+                    Some(remaining) => tag = remaining.split_once('.').map(|(_, tail)| tail),
+                    None => return false,
+                }
+            }
+        }
+        let Some(tag) = tag else {
+            return false;
+        };
+        let (part, tail) = match tag.split_once('.') {
+            Some((part, tail)) => (part, Some(tail)),
+            None => (tag, None),
+        };
+        (first == "*" || first == part) && Self::matches_parts(rest, tail)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// An ordered list of sampling rules.
+pub struct Sampling {
+    rules: Vec<(TagPattern, Arc<dyn Sampler>)>,
+}
+
+impl Sampling {
+    /// Create an empty list of rules, which keeps every record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a rule which samples the records whose tags match `pattern` with `sampler`.
+    pub fn rule<S: Sampler + 'static>(mut self, pattern: &str, sampler: S) -> Self {
+        self.rules
+            .push((TagPattern::new(pattern), Arc::new(sampler)));
+        self
+    }
+
+    /// Return true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return true to keep a record of `tag`.
+    /// Records whose tags match no rule are kept.
+    pub fn sample(&self, tag: &str) -> bool {
+        match self.rules.iter().find(|(pattern, _)| pattern.matches(tag)) {
+            Some((_, sampler)) => sampler.sample(tag),
+            None => true,
+        }
+    }
+
+    /// Check the samplers of all the rules.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.rules
+            .iter()
+            .try_for_each(|(_, sampler)| sampler.validate())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_pattern() {
+        let cases = [
+            ("app", "app", true),
+            ("app", "app.access", false),
+            ("app.*", "app.access", true),
+            ("app.*", "app", false),
+            ("app.*", "app.access.slow", false),
+            ("app.**", "app", true),
+            ("app.**", "app.access.slow", true),
+            ("**.slow", "app.access.slow", true),
+            ("**.slow", "app.access", false),
+            ("**", "anything.at.all", true),
+        ];
+        for (pattern, tag, want) in cases {
+            assert_eq!(
+                TagPattern::new(pattern).matches(tag),
+                want,
+                "{} {}",
+                pattern,
+                tag
+            );
+        }
+    }
+
+    #[test]
+    fn test_probabilistic() {
+        assert!((0..100).all(|_| Probabilistic::new(1.0).sample("test")));
+        assert!((0..100).all(|_| !Probabilistic::new(0.0).sample("test")));
+    }
+
+    #[test]
+    fn test_one_in_n() {
+        let sampler = OneInN::new(3);
+        let kept: Vec<_> = (0..7).map(|_| sampler.sample("test")).collect();
+        assert_eq!(kept, [true, false, false, true, false, false, true]);
+        assert!(!OneInN::new(0).sample("test"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let sampler = RateLimit::new(0.001, 2);
+        assert!(sampler.sample("a"));
+        assert!(sampler.sample("b"));
+        assert!(!sampler.sample("a"));
+
+        let sampler = RateLimit::new(0.001, 1).per_tag();
+        assert!(sampler.sample("a"));
+        assert!(sampler.sample("b"));
+        assert!(!sampler.sample("a"));
+    }
+
+    #[test]
+    fn test_validate() {
+        for sampling in [
+            Sampling::new().rule("**", RateLimit::new(0.0, 1)),
+            Sampling::new().rule("**", RateLimit::new(-1.0, 1)),
+            Sampling::new().rule("**", RateLimit::new(f64::NAN, 1)),
+            Sampling::new().rule("**", RateLimit::new(f64::INFINITY, 1)),
+            Sampling::new().rule("**", RateLimit::new(1000.0, 0)),
+            Sampling::new().rule("**", OneInN::new(0)),
+            Sampling::new()
+                .rule("app", OneInN::new(2))
+                .rule("**", Probabilistic::new(1.5)),
+            Sampling::new().rule("**", Probabilistic::new(f64::NAN)),
+        ] {
+            assert!(
+                matches!(sampling.validate(), Err(Error::InvalidConfig(_))),
+                "{:?}",
+                sampling
+            );
+        }
+        assert!(Sampling::new()
+            .rule("debug", Probabilistic::new(0.0))
+            .rule("**", RateLimit::new(0.5, 1))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sampling() {
+        let sampling = Sampling::new()
+            .rule("debug.**", Probabilistic::new(0.0))
+            .rule("**", OneInN::new(2));
+        assert!(!sampling.sample("debug"));
+        assert!(!sampling.sample("debug.sql"));
+        assert!(sampling.sample("app"));
+        assert!(!sampling.sample("app"));
+        assert!(Sampling::new().sample("anything"));
+    }
+}
@@ -0,0 +1,182 @@
+//! Client-side statistics.
+//!
+//! Every [`Client`](crate::Client) counts what its worker does.
+//! A snapshot of the counters is returned by [`Client::stats`](crate::Client::stats).
+//!
+//! With the `metrics` feature, the same values are also reported through the
+//! [metrics](https://docs.rs/metrics) facade with the `tokio_fluent_` prefix.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the ack latency histogram.
+const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A snapshot of the statistics of a client.
+pub struct Stats {
+    /// The number of records accepted into the queue of the worker.
+    pub enqueued: u64,
+    /// The number of records waiting in the queue of the worker.
+    pub queued: u64,
+    /// The number of records written to the connection, including retries.
+    pub sent: u64,
+    /// The number of records acknowledged by the server.
+    pub acked: u64,
+    /// The number of retried writes.
+    pub retried: u64,
+    /// The number of records rejected because the queue of the worker was full.
+    pub dropped_overflow: u64,
+    /// The number of records dropped by sampling.
+    pub sampled: u64,
+    /// The number of records dropped by filters.
+    pub filtered: u64,
+    /// The number of records given up because they could not be serialized
+    /// or the retries were exhausted.
+    pub failed: u64,
+    /// The number of bytes written to the connection.
+    pub bytes_written: u64,
+    /// The number of reconnections.
+    pub reconnects: u64,
+    /// The latency between writing a record and receiving its ack.
+    pub ack_latency: Histogram,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A histogram of durations.
+pub struct Histogram {
+    /// Pairs of the upper bound of a bucket and the number of samples in the bucket.
+    /// Samples larger than the last bound are only counted in `count`.
+    pub buckets: Vec<(Duration, u64)>,
+    /// The number of samples.
+    pub count: u64,
+    /// The sum of all samples.
+    pub sum: Duration,
+}
+
+#[derive(Debug, Default)]
+/// Counters shared between a client and its worker.
+pub(crate) struct Metrics {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    acked: AtomicU64,
+    retried: AtomicU64,
+    dropped_overflow: AtomicU64,
+    sampled: AtomicU64,
+    filtered: AtomicU64,
+    failed: AtomicU64,
+    bytes_written: AtomicU64,
+    reconnects: AtomicU64,
+    ack_latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    ack_latency_count: AtomicU64,
+    ack_latency_sum_micros: AtomicU64,
+}
+
+macro_rules! counter {
+    ($field:ident, $name:literal) => {
+        pub(crate) fn $field(&self, n: u64) {
+            self.$field.fetch_add(n, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            metrics::counter!(concat!("tokio_fluent_", $name)).increment(n);
+        }
+    };
+}
+
+impl Metrics {
+    counter!(enqueued, "records_enqueued_total");
+    counter!(sent, "records_sent_total");
+    counter!(acked, "records_acked_total");
+    counter!(retried, "retries_total");
+    counter!(dropped_overflow, "records_dropped_overflow_total");
+    counter!(sampled, "records_sampled_out_total");
+    counter!(filtered, "records_filtered_total");
+    counter!(failed, "records_failed_total");
+    counter!(bytes_written, "bytes_written_total");
+    counter!(reconnects, "reconnects_total");
+
+    pub(crate) fn ack_latency(&self, latency: Duration) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| latency <= *bound) {
+            self.ack_latency_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.ack_latency_count.fetch_add(1, Ordering::Relaxed);
+        self.ack_latency_sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("tokio_fluent_ack_latency_seconds").record(latency.as_secs_f64());
+    }
+
+    pub(crate) fn snapshot(&self, queued: u64) -> Stats {
+        Stats {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            queued,
+            sent: self.sent.load(Ordering::Relaxed),
+            acked: self.acked.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            dropped_overflow: self.dropped_overflow.load(Ordering::Relaxed),
+            sampled: self.sampled.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            ack_latency: Histogram {
+                buckets: LATENCY_BUCKETS
+                    .iter()
+                    .zip(self.ack_latency_buckets.iter())
+                    .map(|(bound, n)| (*bound, n.load(Ordering::Relaxed)))
+                    .collect(),
+                count: self.ack_latency_count.load(Ordering::Relaxed),
+                sum: Duration::from_micros(self.ack_latency_sum_micros.load(Ordering::Relaxed)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = Metrics::default();
+        metrics.enqueued(3);
+        metrics.sent(2);
+        metrics.bytes_written(100);
+        metrics.ack_latency(Duration::from_millis(3));
+        metrics.ack_latency(Duration::from_millis(7));
+        metrics.ack_latency(Duration::from_secs(60));
+
+        let stats = metrics.snapshot(1);
+        assert_eq!(stats.enqueued, 3);
+        assert_eq!(stats.queued, 1);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.bytes_written, 100);
+        assert_eq!(stats.acked, 0);
+        assert_eq!(stats.ack_latency.count, 3);
+        assert_eq!(stats.ack_latency.sum, Duration::from_millis(60010));
+        assert_eq!(stats.ack_latency.buckets[0], (Duration::from_millis(1), 0));
+        assert_eq!(stats.ack_latency.buckets[1], (Duration::from_millis(5), 1));
+        assert_eq!(stats.ack_latency.buckets[2], (Duration::from_millis(10), 1));
+        assert_eq!(
+            stats
+                .ack_latency
+                .buckets
+                .iter()
+                .map(|(_, n)| n)
+                .sum::<u64>(),
+            2
+        );
+    }
+}
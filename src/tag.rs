@@ -0,0 +1,137 @@
+//! Tags of fluent records.
+//!
+//! A tag consists of one or more parts separated by `.`, such as `myapp.access`.
+//! Fluentd routes records by matching their tags against patterns,
+//! so each part must be non-empty and consist of ASCII letters, digits, `_` and `-`.
+//!
+//! The [`TagPolicy`] of [`Config`](crate::Config) decides what a client does with a tag
+//! which breaks the rules.
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What a client does with a tag which is not valid.
+pub enum TagPolicy {
+    /// Reject the record with [`Error::InvalidTag`].
+    Validate,
+    /// Replace invalid characters with `_` and drop empty parts.
+    /// Only a tag without any part is rejected.
+    Sanitize,
+    /// Send the tag as it is.
+    #[default]
+    Unchecked,
+}
+
+impl TagPolicy {
+    /// Apply the policy to `tag`, returning the tag to send.
+    pub fn apply(&self, tag: String) -> Result<String, Error> {
+        match self {
+            TagPolicy::Validate => {
+                validate(&tag)?;
+                Ok(tag)
+            }
+            TagPolicy::Sanitize => sanitize(&tag),
+            TagPolicy::Unchecked => Ok(tag),
+        }
+    }
+}
+
+/// Join `prefix` and `tag` with `.`.
+/// An empty `tag` is kept as an empty part, so [`TagPolicy::Validate`] rejects it.
+pub(crate) fn join(prefix: &str, tag: &str) -> String {
+    let mut joined = String::with_capacity(prefix.len() + 1 + tag.len());
+    joined.push_str(prefix);
+    joined.push('.');
+    joined.push_str(tag);
+    joined
+}
+
+/// Check that `tag` follows the rules of fluentd tags.
+pub fn validate(tag: &str) -> Result<(), Error> {
+    if tag
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(is_valid))
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidTag(tag.to_string()))
+    }
+}
+
+/// Make `tag` follow the rules of fluentd tags, by replacing invalid characters with `_`
+/// and dropping empty parts.
+pub fn sanitize(tag: &str) -> Result<String, Error> {
+    let mut sanitized = String::with_capacity(tag.len());
+    for part in tag.split('.').filter(|part| !part.is_empty()) {
+        if !sanitized.is_empty() {
+            sanitized.push('.');
+        }
+        sanitized.extend(part.chars().map(|c| {
+            if c.is_ascii() && is_valid(c as u8) {
+                c
+            } else {
+                '_'
+            }
+        }));
+    }
+    if sanitized.is_empty() {
+        return Err(Error::InvalidTag(tag.to_string()));
+    }
+    Ok(sanitized)
+}
+
+fn is_valid(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        for tag in ["app", "app.access", "my_app.web-1.GET"] {
+            assert!(validate(tag).is_ok(), "{}", tag);
+        }
+        for tag in [
+            "",
+            ".",
+            "app.",
+            ".app",
+            "app..access",
+            "app access",
+            "app.*",
+            "アプリ",
+        ] {
+            assert!(matches!(validate(tag), Err(Error::InvalidTag(t)) if t == tag));
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("app.access").unwrap(), "app.access");
+        assert_eq!(sanitize(".app..my access.").unwrap(), "app.my_access");
+        assert_eq!(sanitize("my_crate::module").unwrap(), "my_crate__module");
+        assert_eq!(sanitize("アプリ.log").unwrap(), "___.log");
+        assert!(matches!(sanitize(".."), Err(Error::InvalidTag(_))));
+    }
+
+    #[test]
+    fn test_policy() {
+        let tag = || "app access".to_string();
+        assert!(TagPolicy::Validate.apply(tag()).is_err());
+        assert_eq!(TagPolicy::Sanitize.apply(tag()).unwrap(), "app_access");
+        assert_eq!(TagPolicy::Unchecked.apply(tag()).unwrap(), "app access");
+        assert_eq!(join("prefix", "app"), "prefix.app");
+        assert_eq!(join("prefix", ""), "prefix.");
+        assert!(TagPolicy::Validate.apply(join("prefix", "")).is_err());
+        assert_eq!(
+            TagPolicy::Sanitize.apply(join("prefix", "")).unwrap(),
+            "prefix"
+        );
+        assert_eq!(TagPolicy::default(), TagPolicy::Unchecked);
+    }
+}
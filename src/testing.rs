@@ -0,0 +1,619 @@
+//! In-process mock fluentd server for tests.
+//!
+//! This module is available with the `testing` feature.
+//!
+//! [`MockFluentServer`] speaks the
+//! [Forward protocol](https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1).
+//! It decodes Message, Forward, PackedForward and CompressedPackedForward frames,
+//! replies with acks and keeps the received records for assertions.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use tokio_fluent::{Client, Config, FluentClient};
+//! use tokio_fluent::record::Map;
+//! use tokio_fluent::testing::MockFluentServer;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let server = MockFluentServer::bind_tcp().await.unwrap();
+//!     let client = Client::new_tcp(server.local_addr().unwrap(), &Config{..Default::default()})
+//!         .await
+//!         .unwrap();
+//!
+//!     let mut map = Map::new();
+//!     map.insert("age".to_string(), 10.into());
+//!     client.send("fluent.test", map).unwrap();
+//!
+//!     let received = server.wait_for(1, Duration::from_secs(3)).await;
+//!     assert_eq!(received[0].0, "fluent.test");
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{Cursor, ErrorKind, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use rmpv::Value as MsgPackValue;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+    sync::Notify,
+    task::{AbortHandle, JoinHandle},
+    time::{sleep, timeout, Instant},
+};
+
+use crate::record::{Map, Value};
+
+/// A record received by [`MockFluentServer`]: the tag, the event time in seconds and the record.
+pub type ReceivedRecord = (String, i64, Map);
+
+#[derive(Debug, Clone, PartialEq)]
+/// A fault injected into the handling of one frame.
+///
+/// Frames affected by `DropConnection` or `WrongAck` are not recorded,
+/// the same way fluentd does not commit a chunk which was not acked.
+pub enum Fault {
+    /// Close the connection without replying.
+    DropConnection,
+    /// Reply with an ack which does not match the chunk of the frame.
+    WrongAck,
+    /// Wait for the duration before replying.
+    Delay(Duration),
+}
+
+#[derive(Default)]
+struct State {
+    received: Mutex<Vec<ReceivedRecord>>,
+    faults: Mutex<VecDeque<Fault>>,
+    connections: AtomicUsize,
+    handles: Mutex<Vec<AbortHandle>>,
+    notify: Notify,
+}
+
+/// A mock fluentd server listening on TCP or a unix domain socket.
+///
+/// The server stops when it is dropped.
+pub struct MockFluentServer {
+    addr: Option<SocketAddr>,
+    path: Option<PathBuf>,
+    state: Arc<State>,
+    handle: JoinHandle<()>,
+}
+
+impl MockFluentServer {
+    /// Start a server listening on a random TCP port of 127.0.0.1.
+    pub async fn bind_tcp() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State::default());
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handle = tokio::spawn(handle_connection(stream, accept_state.clone()));
+                accept_state
+                    .handles
+                    .lock()
+                    .unwrap()
+                    .push(handle.abort_handle());
+            }
+        });
+
+        Ok(Self {
+            addr: Some(addr),
+            path: None,
+            state,
+            handle,
+        })
+    }
+
+    /// Start a server listening on a unix domain socket at `path`.
+    /// The socket file is removed when the server is dropped.
+    pub async fn bind_unix<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let listener = UnixListener::bind(path.as_ref())?;
+        let state = Arc::new(State::default());
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handle = tokio::spawn(handle_connection(stream, accept_state.clone()));
+                accept_state
+                    .handles
+                    .lock()
+                    .unwrap()
+                    .push(handle.abort_handle());
+            }
+        });
+
+        Ok(Self {
+            addr: None,
+            path: Some(path.as_ref().to_path_buf()),
+            state,
+            handle,
+        })
+    }
+
+    /// The address of the server when it listens on TCP.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// The path of the socket when the server listens on a unix domain socket.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The records received so far, in order of arrival.
+    pub fn received(&self) -> Vec<ReceivedRecord> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    /// The number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::Relaxed)
+    }
+
+    /// Close all the connections accepted so far, without replying to pending frames.
+    pub fn close_connections(&self) {
+        for handle in self.state.handles.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+
+    /// Remove the records received so far.
+    pub fn clear(&self) {
+        self.state.received.lock().unwrap().clear();
+    }
+
+    /// Inject a fault into the handling of the next frame which is not affected by a fault yet.
+    /// Faults are consumed in the order they were injected, one per frame.
+    pub fn inject(&self, fault: Fault) {
+        self.state.faults.lock().unwrap().push_back(fault);
+    }
+
+    /// Wait until at least `n` records are received or `wait` elapses,
+    /// and return the records received so far.
+    pub async fn wait_for(&self, n: usize, wait: Duration) -> Vec<ReceivedRecord> {
+        let deadline = Instant::now() + wait;
+        loop {
+            let notified = self.state.notify.notified();
+            let received = self.received();
+            if received.len() >= n {
+                return received;
+            }
+            if timeout(deadline.saturating_duration_since(Instant::now()), notified)
+                .await
+                .is_err()
+            {
+                return self.received();
+            }
+        }
+    }
+}
+
+impl Drop for MockFluentServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+        self.close_connections();
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn handle_connection<S>(mut stream: S, state: Arc<State>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    state.connections.fetch_add(1, Ordering::Relaxed);
+    let mut buf = Vec::new();
+    loop {
+        let frame = match decode_value(&buf) {
+            Ok(Some((value, consumed))) => {
+                buf.drain(..consumed);
+                value
+            }
+            Ok(None) => match stream.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => continue,
+            },
+            Err(e) => {
+                warn!("mock server failed to decode a frame: {}", e);
+                return;
+            }
+        };
+
+        let (records, chunk) = match decode_frame(frame) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                warn!("mock server received a malformed frame: {}", e);
+                return;
+            }
+        };
+
+        let fault = state.faults.lock().unwrap().pop_front();
+        let ack = match fault {
+            Some(Fault::DropConnection) => return,
+            Some(Fault::WrongAck) => chunk.map(|chunk| format!("{}-wrong", chunk)),
+            Some(Fault::Delay(duration)) => {
+                sleep(duration).await;
+                state.received.lock().unwrap().extend(records);
+                chunk
+            }
+            None => {
+                state.received.lock().unwrap().extend(records);
+                chunk
+            }
+        };
+        state.notify.notify_waiters();
+
+        if let Some(ack) = ack {
+            let response = MsgPackValue::Map(vec![("ack".into(), ack.into())]);
+            let mut bytes = Vec::new();
+            if rmpv::encode::write_value(&mut bytes, &response).is_err()
+                || stream.write_all(&bytes).await.is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Decode one MessagePack value from the head of `buf`.
+/// Returns `None` when `buf` does not contain a complete value yet.
+fn decode_value(buf: &[u8]) -> Result<Option<(MsgPackValue, usize)>, rmpv::decode::Error> {
+    let mut cursor = Cursor::new(buf);
+    match rmpv::decode::read_value(&mut cursor) {
+        Ok(value) => Ok(Some((value, cursor.position() as usize))),
+        Err(rmpv::decode::Error::InvalidMarkerRead(e))
+        | Err(rmpv::decode::Error::InvalidDataRead(e))
+            if e.kind() == ErrorKind::UnexpectedEof =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Decode a frame of any mode into its records and the chunk to ack, if requested.
+fn decode_frame(frame: MsgPackValue) -> Result<(Vec<ReceivedRecord>, Option<String>), String> {
+    let mut items = match frame {
+        MsgPackValue::Array(items) if items.len() >= 2 => items.into_iter(),
+        _ => return Err("frame is not an array".to_string()),
+    };
+    let tag = match items.next() {
+        Some(MsgPackValue::String(tag)) => tag.into_str().ok_or("tag is not utf-8")?,
+        _ => return Err("tag is not a string".to_string()),
+    };
+
+    let mut records = Vec::new();
+    let option = match items.next() {
+        // Forward mode: [tag, [[time, record], ...], option]
+        Some(MsgPackValue::Array(entries)) => {
+            for entry in entries {
+                let (time, record) = decode_entry(entry)?;
+                records.push((tag.clone(), time, record));
+            }
+            items.next()
+        }
+        // PackedForward and CompressedPackedForward mode: [tag, bin, option]
+        Some(MsgPackValue::Binary(entries)) => {
+            let option = items.next();
+            let entries = if is_compressed(&option) {
+                let mut decompressed = Vec::new();
+                flate2::read::MultiGzDecoder::new(entries.as_slice())
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| e.to_string())?;
+                decompressed
+            } else {
+                entries
+            };
+            let mut rest = entries.as_slice();
+            while !rest.is_empty() {
+                let (entry, consumed) = decode_value(rest)
+                    .map_err(|e| e.to_string())?
+                    .ok_or("truncated entry")?;
+                let (time, record) = decode_entry(entry)?;
+                records.push((tag.clone(), time, record));
+                rest = &rest[consumed..];
+            }
+            option
+        }
+        // Message mode: [tag, time, record, option]
+        Some(time) => {
+            let time = decode_time(time)?;
+            let record = decode_map(items.next().ok_or("record is missing")?)?;
+            records.push((tag, time, record));
+            items.next()
+        }
+        None => unreachable!("frame has at least two items"),
+    };
+
+    let chunk = match option {
+        Some(MsgPackValue::Map(entries)) => entries.into_iter().find_map(|(k, v)| {
+            (k.as_str() == Some("chunk"))
+                .then(|| v.as_str().map(|s| s.to_string()))
+                .flatten()
+        }),
+        _ => None,
+    };
+    Ok((records, chunk))
+}
+
+fn is_compressed(option: &Option<MsgPackValue>) -> bool {
+    match option {
+        Some(MsgPackValue::Map(entries)) => entries
+            .iter()
+            .any(|(k, v)| k.as_str() == Some("compressed") && v.as_str() == Some("gzip")),
+        _ => false,
+    }
+}
+
+fn decode_entry(entry: MsgPackValue) -> Result<(i64, Map), String> {
+    match entry {
+        MsgPackValue::Array(items) if items.len() == 2 => {
+            let mut items = items.into_iter();
+            let time = decode_time(items.next().unwrap())?;
+            let record = decode_map(items.next().unwrap())?;
+            Ok((time, record))
+        }
+        _ => Err("entry is not [time, record]".to_string()),
+    }
+}
+
+/// Decode an integer time or an EventTime extension into seconds.
+fn decode_time(time: MsgPackValue) -> Result<i64, String> {
+    match time {
+        MsgPackValue::Integer(i) => i.as_i64().ok_or_else(|| "time is out of range".to_string()),
+        MsgPackValue::Ext(0, data) if data.len() == 8 => {
+            Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i64)
+        }
+        _ => Err("time is neither an integer nor an EventTime".to_string()),
+    }
+}
+
+fn decode_map(value: MsgPackValue) -> Result<Map, String> {
+    match value {
+        MsgPackValue::Map(entries) => {
+            let mut map = Map::new();
+            for (k, v) in entries {
+                let key = match k {
+                    MsgPackValue::String(s) => s.into_str().ok_or("key is not utf-8")?,
+                    _ => return Err("key is not a string".to_string()),
+                };
+                map.insert(key, decode_value_into_record(v)?);
+            }
+            Ok(map)
+        }
+        _ => Err("record is not a map".to_string()),
+    }
+}
+
+fn decode_value_into_record(value: MsgPackValue) -> Result<Value, String> {
+    match value {
+        MsgPackValue::Boolean(b) => Ok(b.into()),
+        MsgPackValue::Integer(i) => match i.as_i64() {
+            Some(i) => Ok(i.into()),
+            None => Ok(i.as_u64().ok_or("integer is out of range")?.into()),
+        },
+        MsgPackValue::F32(f) => Ok(f.into()),
+        MsgPackValue::F64(f) => Ok(f.into()),
+        MsgPackValue::String(s) => Ok(s.into_str().ok_or("string is not utf-8")?.into()),
+        MsgPackValue::Binary(b) => Ok(String::from_utf8_lossy(&b).into_owned().into()),
+        MsgPackValue::Array(items) => Ok(items
+            .into_iter()
+            .map(decode_value_into_record)
+            .collect::<Result<Vec<_>, _>>()?
+            .into()),
+        value @ MsgPackValue::Map(_) => Ok(decode_map(value)?.into()),
+        MsgPackValue::Nil | MsgPackValue::Ext(_, _) => {
+            Err("nil and extension values are not supported".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tokio::net::TcpStream;
+
+    use super::*;
+    use crate::client::{Client, Config, FluentClient};
+
+    const WAIT: Duration = Duration::from_secs(3);
+
+    fn encode(value: MsgPackValue) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &value).unwrap();
+        bytes
+    }
+
+    fn entry(time: i64, age: i64) -> MsgPackValue {
+        MsgPackValue::Array(vec![
+            time.into(),
+            MsgPackValue::Map(vec![("age".into(), age.into())]),
+        ])
+    }
+
+    fn chunk_option(chunk: &str) -> Vec<(MsgPackValue, MsgPackValue)> {
+        vec![("chunk".into(), chunk.into())]
+    }
+
+    async fn read_ack(stream: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        loop {
+            if let Ok(Some((MsgPackValue::Map(entries), _))) = decode_value(&buf) {
+                return entries[0].1.as_str().unwrap().to_string();
+            }
+            assert_ne!(
+                stream.read_buf(&mut buf).await.unwrap(),
+                0,
+                "connection closed"
+            );
+        }
+    }
+
+    fn age(age: i64) -> Map {
+        let mut map = Map::new();
+        map.insert("age".to_string(), age.into());
+        map
+    }
+
+    #[tokio::test]
+    async fn test_receive_from_client() {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        client.send("test.a", age(1)).unwrap();
+        client.send("test.b", age(2)).unwrap();
+
+        let received = server.wait_for(2, WAIT).await;
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].0, "test.a");
+        assert_eq!(received[0].2, age(1));
+        assert_eq!(received[1].0, "test.b");
+        assert_eq!(received[1].2, age(2));
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receive_from_client_over_unix_socket() {
+        let path =
+            std::env::temp_dir().join(format!("tokio-fluent-{:016x}.sock", fastrand::u64(..)));
+        let server = MockFluentServer::bind_unix(&path).await.unwrap();
+        let client = Client::new_unix(server.path().unwrap(), &Config::default())
+            .await
+            .unwrap();
+
+        client.send("test", age(1)).unwrap();
+
+        let received = server.wait_for(1, WAIT).await;
+        assert_eq!(received, vec![("test".to_string(), received[0].1, age(1))]);
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_receive_forward_modes() {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let mut stream = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        // Forward
+        let frame = MsgPackValue::Array(vec![
+            "forward".into(),
+            MsgPackValue::Array(vec![entry(1, 1), entry(2, 2)]),
+            MsgPackValue::Map(chunk_option("c1")),
+        ]);
+        stream.write_all(&encode(frame)).await.unwrap();
+        assert_eq!(read_ack(&mut stream).await, "c1");
+
+        // PackedForward
+        let mut packed = encode(entry(3, 3));
+        packed.extend(encode(entry(4, 4)));
+        let frame = MsgPackValue::Array(vec![
+            "packed".into(),
+            MsgPackValue::Binary(packed.clone()),
+            MsgPackValue::Map(chunk_option("c2")),
+        ]);
+        stream.write_all(&encode(frame)).await.unwrap();
+        assert_eq!(read_ack(&mut stream).await, "c2");
+
+        // CompressedPackedForward
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&packed).unwrap();
+        let mut option = chunk_option("c3");
+        option.push(("compressed".into(), "gzip".into()));
+        let frame = MsgPackValue::Array(vec![
+            "compressed".into(),
+            MsgPackValue::Binary(encoder.finish().unwrap()),
+            MsgPackValue::Map(option),
+        ]);
+        stream.write_all(&encode(frame)).await.unwrap();
+        assert_eq!(read_ack(&mut stream).await, "c3");
+
+        let received = server.wait_for(6, WAIT).await;
+        assert_eq!(
+            received,
+            vec![
+                ("forward".to_string(), 1, age(1)),
+                ("forward".to_string(), 2, age(2)),
+                ("packed".to_string(), 3, age(3)),
+                ("packed".to_string(), 4, age(4)),
+                ("compressed".to_string(), 3, age(3)),
+                ("compressed".to_string(), 4, age(4)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_event_time() {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let mut stream = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let mut time = 1234567u32.to_be_bytes().to_vec();
+        time.extend(500u32.to_be_bytes());
+        let frame = MsgPackValue::Array(vec![
+            "message".into(),
+            MsgPackValue::Ext(0, time),
+            MsgPackValue::Map(vec![("age".into(), 1.into())]),
+            MsgPackValue::Map(chunk_option("c1")),
+        ]);
+        stream.write_all(&encode(frame)).await.unwrap();
+        assert_eq!(read_ack(&mut stream).await, "c1");
+
+        assert_eq!(
+            server.wait_for(1, WAIT).await,
+            vec![("message".to_string(), 1234567, age(1))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let mut stream = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        let frame = |chunk: &str| {
+            encode(MsgPackValue::Array(vec![
+                "test".into(),
+                1.into(),
+                MsgPackValue::Map(vec![("age".into(), 1.into())]),
+                MsgPackValue::Map(chunk_option(chunk)),
+            ]))
+        };
+
+        server.inject(Fault::WrongAck);
+        server.inject(Fault::Delay(Duration::from_millis(100)));
+        server.inject(Fault::DropConnection);
+
+        stream.write_all(&frame("c1")).await.unwrap();
+        assert_ne!(read_ack(&mut stream).await, "c1");
+        assert!(server.received().is_empty());
+
+        let started = Instant::now();
+        stream.write_all(&frame("c2")).await.unwrap();
+        assert_eq!(read_ack(&mut stream).await, "c2");
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(server.received().len(), 1);
+
+        stream.write_all(&frame("c3")).await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(stream.read_buf(&mut buf).await.unwrap(), 0);
+        assert_eq!(server.received().len(), 1);
+    }
+}
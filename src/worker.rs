@@ -1,83 +1,137 @@
 use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::Cursor;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
-use anyhow::Result as AnyhowResult;
 use async_trait::async_trait;
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, BytesMut};
 use log::{debug, warn};
-use rmp_serde::Serializer;
-use serde::{ser::SerializeMap, Deserialize, Serialize};
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpStream, UnixStream},
-    sync::broadcast::{error::RecvError, Receiver},
-    time::{timeout, Duration},
+    net::{TcpSocket, TcpStream, UnixStream},
+    sync::{
+        mpsc::{Receiver, UnboundedSender},
+        oneshot,
+    },
+    time::{timeout, timeout_at, Duration, Interval, MissedTickBehavior},
 };
 
-use crate::record::Map;
+use crate::client::{Config, TcpOptions};
+use crate::error::{Chain, Error};
+use crate::record::{EncodedMap, Map};
+use crate::retry::RetryPolicy;
+use crate::stats::Metrics;
 
-const RETRY_INCREMENT_RATE: f64 = 1.5;
-
-#[derive(Debug, Clone)]
-pub enum Error {
-    WriteFailed(String),
-    ReadFailed(String),
-    AckUnmatched(String, String),
-    MaxRetriesExceeded,
-    ConnectionClosed,
+#[derive(Clone, Debug)]
+/// A record sent by a client.
+pub struct Record {
+    pub tag: String,
+    pub timestamp: i64,
+    pub record: Map,
+    /// The body encoded in advance. When it is set, it is sent instead of `record`, which is empty.
+    pub encoded: Option<EncodedMap>,
 }
 
-impl std::error::Error for Error {}
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// The id of a chunk, used to match an ack to its record.
+///
+/// The ids of a worker share their prefix and end with a fixed-width counter,
+/// so they are ordered as they were generated.
+struct ChunkId([u8; 32]);
 
-impl std::fmt::Display for Error {
+impl ChunkId {
+    fn as_str(&self) -> &str {
+        // only hex digits are written to the array
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    /// The id returned in an ack, or None if it cannot be an id of this client.
+    fn parse(ack: &str) -> Option<Self> {
+        ack.as_bytes().try_into().ok().map(ChunkId)
+    }
+}
+
+impl std::fmt::Debug for ChunkId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match *self {
-            Error::WriteFailed(ref e) => e,
-            Error::ReadFailed(ref e) => e,
-            Error::AckUnmatched(_, _) => "request chunk and response ack did not match",
-            Error::MaxRetriesExceeded => "max retries exceeded",
-            Error::ConnectionClosed => "connection closed",
-        };
-        write!(f, "{}", s)
+        f.write_str(self.as_str())
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
-pub struct Record {
-    pub tag: String,
-    pub timestamp: i64,
-    pub record: Map,
-    pub options: Options,
+/// A generator of unique chunk ids.
+///
+/// An id is the random prefix of the worker followed by a counter, both in hex,
+/// so generating it needs neither a random number nor an allocation.
+struct ChunkIds {
+    prefix: u64,
+    next: u64,
 }
 
-#[derive(Clone, Debug)]
-pub struct Options {
-    pub chunk: String,
+impl ChunkIds {
+    fn new() -> Self {
+        Self {
+            prefix: fastrand::u64(..),
+            next: 0,
+        }
+    }
+
+    fn next(&mut self) -> ChunkId {
+        let mut id = [0u8; 32];
+        for (i, n) in [self.prefix, self.next].into_iter().enumerate() {
+            for (j, digit) in id[i * 16..(i + 1) * 16].iter_mut().enumerate() {
+                *digit = HEX_DIGITS[(n >> (60 - j * 4)) as usize & 0xf];
+            }
+        }
+        self.next = self.next.wrapping_add(1);
+        ChunkId(id)
+    }
 }
 
-impl Serialize for Options {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut map = serializer.serialize_map(Some(1))?;
-        map.serialize_entry("chunk", &self.chunk)?;
-        map.end()
+/// A handler of the records which the worker failed to send.
+///
+/// It is implemented for closures and for unbounded channel senders.
+pub trait ErrorHandler: Send + Sync {
+    fn handle(&self, record: Record, error: Error);
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(Record, Error) + Send + Sync,
+{
+    fn handle(&self, record: Record, error: Error) {
+        self(record, error)
+    }
+}
+
+impl ErrorHandler for UnboundedSender<(Record, Error)> {
+    fn handle(&self, record: Record, error: Error) {
+        let _ = self.send((record, error));
+    }
+}
+
+impl std::fmt::Debug for dyn ErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ErrorHandler")
     }
 }
 
-#[derive(Clone)]
 pub enum Message {
     Record(Record),
+    /// Notify the sender after all the preceding messages are handled.
+    Flush(oneshot::Sender<()>),
     Terminate,
 }
 
 #[derive(Debug)]
 struct SerializedRecord {
     record: bytes::Bytes,
-    chunk: String,
+    chunk: ChunkId,
 }
 
 #[derive(Debug, Deserialize)]
@@ -85,19 +139,44 @@ struct AckResponse {
     ack: String,
 }
 
-pub struct RetryConfig {
-    pub initial_wait: u64,
-    pub max: u32,
-    pub max_wait: u64,
+/// A record written to the connection whose ack has not been received yet.
+struct InFlight {
+    record: Record,
+    serialized: SerializedRecord,
+    sent_at: Instant,
+}
+
+/// Timeouts of an attempt to write a record. Zero means no timeout.
+struct Timeouts {
+    write: Duration,
+    ack: Duration,
 }
 
 pub struct Worker<StreamType> {
     stream_config: Arc<dyn Connectable<StreamType> + Send + Sync>,
     max_connection_lifetime: Duration,
+    health_check_interval: Duration,
+    timeouts: Timeouts,
+    max_in_flight: usize,
     stream: Cell<StreamType>,
+    connected: bool,
     last_connection_time: Cell<Instant>,
+    last_write_time: Instant,
     receiver: Receiver<Message>,
-    retry_config: RetryConfig,
+    chunk_ids: ChunkIds,
+    /// The buffer records are encoded into. Its memory is reused once the records are acked.
+    encode_buf: BytesMut,
+    /// Records waiting for their acks, keyed by their chunk ids, so in the order they were written.
+    pending: BTreeMap<ChunkId, InFlight>,
+    /// Bytes of acks read from the connection but not decoded yet.
+    ack_buf: BytesMut,
+    retry_policy: Arc<dyn RetryPolicy>,
+    /// The number of failed attempts since the last ack.
+    attempt: u32,
+    /// The last wait returned by the retry policy.
+    wait: Duration,
+    metrics: Arc<Metrics>,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
 }
 
 impl<StreamType> Worker<StreamType>
@@ -106,148 +185,444 @@ where
 {
     pub async fn new(
         stream_config: Arc<dyn Connectable<StreamType> + Send + Sync>,
-        max_connection_lifetime: Duration,
         receiver: Receiver<Message>,
-        retry_config: RetryConfig,
-    ) -> AnyhowResult<Self> {
+        config: &Config,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, Error> {
         let stream = stream_config.connect().await?;
+        let max_in_flight = config.max_in_flight.max(1);
         Ok(Self {
             stream_config,
-            max_connection_lifetime,
+            max_connection_lifetime: config.max_connection_lifetime,
+            health_check_interval: config.health_check_interval,
+            timeouts: Timeouts {
+                write: config.write_timeout,
+                ack: config.ack_timeout,
+            },
+            max_in_flight,
             stream: Cell::new(stream),
+            connected: true,
             last_connection_time: Cell::new(Instant::now()),
+            last_write_time: Instant::now(),
             receiver,
-            retry_config,
+            chunk_ids: ChunkIds::new(),
+            encode_buf: BytesMut::new(),
+            pending: BTreeMap::new(),
+            ack_buf: BytesMut::with_capacity(64),
+            retry_policy: config.retry_policy.clone(),
+            attempt: 0,
+            wait: Duration::ZERO,
+            metrics,
+            error_handler: config.error_handler.clone(),
         })
     }
 
     pub async fn run(&mut self) {
+        let mut health_check = (!self.health_check_interval.is_zero()).then(|| {
+            let mut interval = tokio::time::interval(self.health_check_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
         loop {
-            match self.receiver.recv().await {
-                Ok(Message::Record(record)) => {
-                    let record = match self.encode(record) {
-                        Ok(record) => record,
-                        Err(e) => {
-                            warn!("failed to serialize a message: {}", e);
-                            continue;
-                        }
-                    };
-
-                    match self.write_with_retry(&record).await {
-                        Ok(_) => {}
-                        Err(_) => continue,
-                    };
+            let deadline = self.ack_deadline();
+            let message = tokio::select! {
+                message = self.receiver.recv(), if self.pending.len() < self.max_in_flight => message,
+                ack = read_ack(self.stream.get_mut(), &mut self.ack_buf, deadline, self.timeouts.ack),
+                    if !self.pending.is_empty() =>
+                {
+                    if let Err(e) = ack.and_then(|ack| self.acknowledge(ack)) {
+                        self.recover(e).await;
+                    }
+                    continue;
+                }
+                _ = tick(&mut health_check), if self.pending.is_empty() => {
+                    self.check_health().await;
+                    continue;
+                }
+            };
+            match message {
+                Some(Message::Record(record)) => {
+                    self.send(record).await;
                 }
-                Err(RecvError::Closed) | Ok(Message::Terminate) => {
+                Some(Message::Flush(done)) => {
+                    self.drain().await;
+                    let _ = done.send(());
+                }
+                None | Some(Message::Terminate) => {
+                    self.drain().await;
                     break;
                 }
-                Err(RecvError::Lagged(_)) => continue,
             }
         }
     }
 
-    fn encode(&self, record: Record) -> Result<SerializedRecord, rmp_serde::encode::Error> {
-        let mut writer = bytes::BytesMut::new().writer();
-        record.serialize(&mut Serializer::new(&mut writer))?;
+    /// Probe the connection when nothing has been written for `health_check_interval`,
+    /// and replace it when the server has closed it or it is broken.
+    async fn check_health(&mut self) {
+        if self.connected && self.last_write_time.elapsed() < self.health_check_interval {
+            return;
+        }
+        if self.connected && Self::is_alive(self.stream.get_mut()).await {
+            return;
+        }
+        debug!("connection is dead, replacing it");
+        self.connected = false;
+        if let Err(err) = self.reconnect().await {
+            warn!(
+                "failed to reconnect. Will try again upon the next health check: {}",
+                Chain(&err)
+            );
+        }
+    }
+
+    /// The server never sends data unless it is asked for an ack,
+    /// so a readable connection means that it was closed or broken.
+    async fn is_alive(stream: &mut StreamType) -> bool {
+        let mut buf = [0u8; 64];
+        !matches!(
+            timeout(Duration::ZERO, stream.read(&mut buf)).await,
+            Ok(Ok(0)) | Ok(Err(_))
+        )
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        debug!("attempting to re-establish connection");
+        let stream = self.stream_config.connect().await?;
+        self.stream.replace(stream);
+        self.ack_buf.clear();
+        self.connected = true;
+        self.last_connection_time.replace(Instant::now());
+        self.metrics.reconnects(1);
+        Ok(())
+    }
+
+    fn encode(&mut self, record: &Record) -> Result<SerializedRecord, rmp_serde::encode::Error> {
+        let chunk = self.chunk_ids.next();
+        let result = encode_message(&mut self.encode_buf, record, &chunk);
+        let encoded = self.encode_buf.split().freeze();
+        result?;
         Ok(SerializedRecord {
-            record: writer.into_inner().freeze(),
-            chunk: record.options.chunk,
+            record: encoded,
+            chunk,
         })
     }
 
-    async fn write_with_retry(&mut self, record: &SerializedRecord) -> Result<(), Error> {
-        let mut wait_time = Duration::from_millis(0);
-        for i in 0..self.retry_config.max as i32 {
-            tokio::time::sleep(wait_time).await;
-
-            // reconnect when the lifetime is reached
-            if !self.max_connection_lifetime.is_zero()
-                && self.last_connection_time.get().elapsed() >= self.max_connection_lifetime
-            {
-                debug!("attempting to re-establish connection");
-                match self.stream_config.connect().await {
-                    Ok(new_stream) => {
-                        self.stream.replace(new_stream);
-                        self.last_connection_time.replace(Instant::now());
-                    }
-                    Err(err) => {
-                        warn!(
-                            "failed to reconnect. Will try again upon the next try-write: {}",
-                            err
-                        );
-                    }
-                }
-            }
+    fn fail(&self, record: Record, error: Error) {
+        self.metrics.failed(1);
+        if let Some(handler) = &self.error_handler {
+            handler.handle(record, error);
+        }
+    }
 
-            match Self::write(&mut self.stream.get_mut(), record).await {
-                Ok(_) => return Ok(()),
-                Err(Error::ConnectionClosed) => return Err(Error::ConnectionClosed),
-                Err(_) => {}
+    /// Write a record and add it to the pending records.
+    async fn send(&mut self, record: Record) {
+        let serialized = match self.encode(&record) {
+            Ok(serialized) => serialized,
+            Err(e) => {
+                warn!("failed to serialize a message: {}", e);
+                self.fail(record, Error::Serialization(e));
+                return;
             }
+        };
 
-            let mut t =
-                (self.retry_config.initial_wait as f64 * RETRY_INCREMENT_RATE.powi(i - 1)) as u64;
-            if t > self.retry_config.max_wait {
-                t = self.retry_config.max_wait;
-            }
-            wait_time = Duration::from_millis(t);
+        let prepared = self.prepare_connection().await;
+        let chunk = serialized.chunk;
+        self.pending.insert(
+            chunk,
+            InFlight {
+                record,
+                serialized,
+                sent_at: Instant::now(),
+            },
+        );
+        let result = match prepared {
+            Ok(_) => self.write_pending(chunk).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.recover(e).await;
         }
-        warn!("write's max retries exceeded.");
-        Err(Error::MaxRetriesExceeded)
     }
 
-    async fn write(stream: &mut StreamType, record: &SerializedRecord) -> Result<(), Error> {
-        stream
-            .write_all(record.record.chunk())
+    /// Wait for the acks of all the pending records.
+    async fn drain(&mut self) {
+        while !self.pending.is_empty() {
+            let deadline = self.ack_deadline();
+            let result = read_ack(
+                self.stream.get_mut(),
+                &mut self.ack_buf,
+                deadline,
+                self.timeouts.ack,
+            )
             .await
-            .map_err(|e| Error::WriteFailed(e.to_string()))?;
+            .and_then(|ack| self.acknowledge(ack));
+            if let Err(e) = result {
+                self.recover(e).await;
+            }
+        }
+    }
+
+    /// Remove the pending record matching `ack`.
+    ///
+    /// The pending records are always written again on a new connection,
+    /// so an ack matching none of them is a protocol error, which is recovered from by retrying.
+    fn acknowledge(&mut self, ack: AckResponse) -> Result<(), Error> {
+        let Some(in_flight) =
+            ChunkId::parse(&ack.ack).and_then(|chunk| self.pending.remove(&chunk))
+        else {
+            warn!("ack did not match any pending chunk. ack: {}", ack.ack);
+            return Err(Error::AckMismatch { ack: ack.ack });
+        };
+        self.metrics.acked(1);
+        self.metrics.ack_latency(in_flight.sent_at.elapsed());
+        self.attempt = 0;
+        self.wait = Duration::ZERO;
+        Ok(())
+    }
 
-        let received_ack = Self::read_ack(stream).await?;
+    /// The deadline of the ack of the oldest pending record.
+    fn ack_deadline(&self) -> Option<Instant> {
+        if self.timeouts.ack.is_zero() {
+            return None;
+        }
+        self.pending
+            .values()
+            .map(|in_flight| in_flight.sent_at)
+            .min()
+            .map(|sent_at| sent_at + self.timeouts.ack)
+    }
 
-        if received_ack.ack != record.chunk {
-            warn!(
-                "ack and chunk did not match. ack: {}, chunk: {}",
-                received_ack.ack, record.chunk
+    /// Retry the pending records on a new connection until they are written again,
+    /// or give them up when the retry policy says so.
+    async fn recover(&mut self, mut error: Error) {
+        loop {
+            // the state of the connection is unknown, so use a new one for the next attempt
+            self.connected = false;
+            self.attempt = self.attempt.saturating_add(1);
+            match self.retry_policy.next_wait(self.attempt, self.wait) {
+                Some(next) => self.wait = next,
+                None => {
+                    warn!("write's max retries exceeded.");
+                    self.give_up(error);
+                    return;
+                }
+            }
+            tokio::time::sleep(self.wait).await;
+            self.metrics.retried(self.pending.len() as u64);
+
+            match self.resend().await {
+                Ok(_) => return,
+                Err(e) => error = e,
+            }
+        }
+    }
+
+    /// Write all the pending records to a new connection.
+    async fn resend(&mut self) -> Result<(), Error> {
+        self.prepare_connection().await?;
+        let chunks: Vec<ChunkId> = self.pending.keys().copied().collect();
+        for chunk in chunks {
+            self.write_pending(chunk).await?;
+        }
+        Ok(())
+    }
+
+    fn give_up(&mut self, error: Error) {
+        let retries = self.attempt - 1;
+        let mut error = Some(error);
+        while let Some((_, in_flight)) = self.pending.pop_first() {
+            let last = if self.pending.is_empty() {
+                error.take()
+            } else {
+                error.clone()
+            };
+            self.fail(
+                in_flight.record,
+                Error::MaxRetriesExceeded {
+                    retries,
+                    last: last.map(Box::new),
+                },
             );
-            return Err(Error::AckUnmatched(received_ack.ack, record.chunk.clone()));
         }
+        self.attempt = 0;
+        self.wait = Duration::ZERO;
+    }
+
+    /// Reconnect when the last attempt failed, or when the lifetime of the connection
+    /// is reached and no record is waiting for its ack.
+    async fn prepare_connection(&mut self) -> Result<(), Error> {
+        if !self.connected {
+            return self.reconnect().await;
+        }
+        if !self.max_connection_lifetime.is_zero()
+            && self.pending.is_empty()
+            && self.last_connection_time.get().elapsed() >= self.max_connection_lifetime
+        {
+            if let Err(err) = self.reconnect().await {
+                warn!(
+                    "failed to reconnect. Will try again upon the next try-write: {}",
+                    Chain(&err)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_pending(&mut self, chunk: ChunkId) -> Result<(), Error> {
+        let Some(in_flight) = self.pending.get_mut(&chunk) else {
+            return Ok(());
+        };
+        let stream = self.stream.get_mut();
+        let result = with_timeout(
+            self.timeouts.write,
+            async { Ok(stream.write_all(&in_flight.serialized.record).await?) },
+            Error::WriteTimeout(self.timeouts.write),
+        )
+        .await;
+        self.last_write_time = Instant::now();
+        result?;
+        in_flight.sent_at = self.last_write_time;
+        self.metrics.sent(1);
+        self.metrics
+            .bytes_written(in_flight.serialized.record.len() as u64);
         Ok(())
     }
+}
+
+/// Encode a record in Message mode: `[tag, time, record, {"chunk": chunk}]`.
+fn encode_message(
+    buf: &mut BytesMut,
+    record: &Record,
+    chunk: &ChunkId,
+) -> Result<(), rmp_serde::encode::Error> {
+    let mut writer = buf.writer();
+    rmp::encode::write_array_len(&mut writer, 4)?;
+    rmp::encode::write_str(&mut writer, &record.tag)?;
+    rmp::encode::write_sint(&mut writer, record.timestamp)?;
+    match &record.encoded {
+        Some(encoded) => writer.get_mut().put_slice(encoded.as_bytes()),
+        None => record.record.serialize(&mut Serializer::new(&mut writer))?,
+    }
+    rmp::encode::write_map_len(&mut writer, 1)?;
+    rmp::encode::write_str(&mut writer, "chunk")?;
+    rmp::encode::write_str(&mut writer, chunk.as_str())?;
+    Ok(())
+}
 
-    async fn read_ack(stream: &mut StreamType) -> Result<AckResponse, Error> {
-        let mut buf = bytes::BytesMut::with_capacity(64);
+/// Read the next ack from the connection.
+///
+/// Bytes are read into `buf`, so it is safe to cancel this future and call it again.
+async fn read_ack<StreamType>(
+    stream: &mut StreamType,
+    buf: &mut BytesMut,
+    deadline: Option<Instant>,
+    ack_timeout: Duration,
+) -> Result<AckResponse, Error>
+where
+    StreamType: AsyncReadExt + Unpin,
+{
+    let read = async {
         loop {
-            if let Ok(ack) = rmp_serde::from_slice::<AckResponse>(&buf) {
+            let mut cursor = Cursor::new(&buf[..]);
+            if let Ok(ack) = AckResponse::deserialize(&mut Deserializer::new(&mut cursor)) {
+                let consumed = cursor.position() as usize;
+                buf.advance(consumed);
                 return Ok(ack);
             }
 
-            if stream
-                .read_buf(&mut buf)
-                .await
-                .map_err(|e| Error::ReadFailed(e.to_string()))?
-                == 0
-            {
+            if stream.read_buf(buf).await? == 0 {
                 return Err(Error::ConnectionClosed);
             }
         }
+    };
+    match deadline {
+        Some(deadline) => timeout_at(deadline.into(), read)
+            .await
+            .unwrap_or(Err(Error::AckTimeout(ack_timeout))),
+        None => read.await,
+    }
+}
+
+/// Run `future` with `duration` as the timeout, or without timeout when `duration` is zero.
+async fn with_timeout<T>(
+    duration: Duration,
+    future: impl std::future::Future<Output = Result<T, Error>>,
+    on_timeout: Error,
+) -> Result<T, Error> {
+    if duration.is_zero() {
+        return future.await;
+    }
+    match timeout(duration, future).await {
+        Ok(result) => result,
+        Err(_) => Err(on_timeout),
+    }
+}
+
+/// Wait for the next tick of `interval`, or forever when it is disabled.
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
     }
 }
 
 #[async_trait]
 pub trait Connectable<T> {
-    async fn connect(&self) -> AnyhowResult<T>;
+    async fn connect(&self) -> Result<T, Error>;
 }
 
 #[derive(Debug)]
 pub struct TCPConnectionConfig {
     pub addr: std::net::SocketAddr,
     pub timeout: Duration,
+    pub options: TcpOptions,
+}
+
+impl TCPConnectionConfig {
+    fn socket(&self) -> std::io::Result<TcpSocket> {
+        let socket = if self.addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        let options = &self.options;
+        let sock_ref = SockRef::from(&socket);
+        sock_ref.set_tcp_nodelay(options.nodelay)?;
+        if let Some(keepalive) = &options.keepalive {
+            let mut params = TcpKeepalive::new().with_time(keepalive.idle);
+            if let Some(interval) = keepalive.interval {
+                params = params.with_interval(interval);
+            }
+            if let Some(count) = keepalive.count {
+                params = params.with_retries(count);
+            }
+            sock_ref.set_tcp_keepalive(&params)?;
+        }
+        if let Some(size) = options.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = options.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(linger) = options.linger {
+            sock_ref.set_linger(Some(linger))?;
+        }
+        if let Some(local_addr) = options.local_addr {
+            socket.bind(local_addr)?;
+        }
+        Ok(socket)
+    }
 }
 
 #[async_trait]
 impl Connectable<TcpStream> for TCPConnectionConfig {
-    async fn connect(&self) -> AnyhowResult<TcpStream> {
-        let stream = timeout(self.timeout, TcpStream::connect(self.addr)).await??;
+    async fn connect(&self) -> Result<TcpStream, Error> {
+        let socket = self.socket()?;
+        let stream = timeout(self.timeout, socket.connect(self.addr))
+            .await
+            .map_err(|_| Error::ConnectTimeout(self.timeout))??;
         Ok(stream)
     }
 }
@@ -260,8 +635,362 @@ pub struct UnixSocketConfig {
 
 #[async_trait]
 impl Connectable<UnixStream> for UnixSocketConfig {
-    async fn connect(&self) -> AnyhowResult<UnixStream> {
-        let stream = timeout(self.timeout, UnixStream::connect(self.path.as_path())).await??;
+    async fn connect(&self) -> Result<UnixStream, Error> {
+        let stream = timeout(self.timeout, UnixStream::connect(self.path.as_path()))
+            .await
+            .map_err(|_| Error::ConnectTimeout(self.timeout))??;
         Ok(stream)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::client::{AsyncFluentClient, Client, TcpKeepaliveOptions};
+    use crate::retry::ConstantBackoff;
+    use crate::testing::{Fault, MockFluentServer};
+
+    /// Start a mock server and connect a client with `config` to it.
+    async fn connect(config: Config) -> (MockFluentServer, Client) {
+        let server = MockFluentServer::bind_tcp().await.unwrap();
+        let client = Client::new_tcp(server.local_addr().unwrap(), &config)
+            .await
+            .unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn test_chunk_ids() {
+        let mut ids = ChunkIds {
+            prefix: 0x0123_4567_89ab_cdef,
+            next: 0xff,
+        };
+        assert_eq!(ids.next().as_str(), "0123456789abcdef00000000000000ff");
+        assert_eq!(ids.next().as_str(), "0123456789abcdef0000000000000100");
+
+        let mut other = ChunkIds::new();
+        assert_ne!(other.next(), ChunkIds::new().next());
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let mut map = Map::new();
+        map.insert("age".to_string(), 22.into());
+        let mut record = Record {
+            tag: "test".to_string(),
+            timestamp: 1_700_000_000,
+            record: map.clone(),
+            encoded: None,
+        };
+        let chunk = ChunkIds::new().next();
+        let mut options = std::collections::HashMap::new();
+        options.insert("chunk", chunk.as_str());
+        let want = rmp_serde::to_vec(&("test", 1_700_000_000i64, &map, options)).unwrap();
+
+        let mut buf = BytesMut::new();
+        encode_message(&mut buf, &record, &chunk).unwrap();
+        assert_eq!(buf.split(), want);
+
+        record.record = Map::new();
+        record.encoded = Some(map.encode().unwrap());
+        encode_message(&mut buf, &record, &chunk).unwrap();
+        assert_eq!(buf, want);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = local.local_addr().unwrap();
+        drop(local);
+
+        let config = TCPConnectionConfig {
+            addr: listener.local_addr().unwrap(),
+            timeout: Duration::from_secs(3),
+            options: TcpOptions {
+                nodelay: true,
+                keepalive: Some(TcpKeepaliveOptions {
+                    idle: Duration::from_secs(30),
+                    interval: Some(Duration::from_secs(5)),
+                    count: Some(3),
+                }),
+                send_buffer_size: Some(64 * 1024),
+                recv_buffer_size: Some(64 * 1024),
+                local_addr: Some(local_addr),
+                linger: Some(Duration::from_secs(1)),
+            },
+        };
+        let stream = config.connect().await.unwrap();
+
+        let sock_ref = SockRef::from(&stream);
+        assert!(sock_ref.tcp_nodelay().unwrap());
+        assert!(sock_ref.keepalive().unwrap());
+        assert_eq!(
+            sock_ref.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            sock_ref.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(sock_ref.tcp_keepalive_retries().unwrap(), 3);
+        assert!(sock_ref.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(sock_ref.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(sock_ref.linger().unwrap(), Some(Duration::from_secs(1)));
+        assert_eq!(stream.local_addr().unwrap(), local_addr);
+    }
+
+    #[tokio::test]
+    async fn test_error_handler() {
+        let (failed, mut failures) = unbounded_channel();
+        let config = Config {
+            retry_policy: Arc::new(ConstantBackoff::new(Duration::ZERO).with_max_retries(0)),
+            error_handler: Some(Arc::new(failed)),
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        server.inject(Fault::DropConnection);
+        let mut map = Map::new();
+        map.insert("age".to_string(), 10.into());
+        AsyncFluentClient::send(&client, "test", map.clone())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        let (record, error) = failures.try_recv().expect("failed record was not handled");
+        assert_eq!(record.tag, "test");
+        assert_eq!(record.record, map);
+        match error {
+            Error::MaxRetriesExceeded { retries, last } => {
+                assert_eq!(retries, 0);
+                assert!(matches!(last.as_deref(), Some(Error::ConnectionClosed)));
+            }
+            e => unreachable!("unexpected error: {}", e),
+        }
+        assert_eq!(client.stats().failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_connection_closed() {
+        let config = Config {
+            retry_policy: Arc::new(ConstantBackoff::new(Duration::ZERO)),
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        server.inject(Fault::DropConnection);
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        assert_eq!(server.received().len(), 1);
+        assert_eq!(server.connections(), 2);
+        assert_eq!(client.stats().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let config = Config {
+            max_in_flight: 8,
+            retry_policy: Arc::new(ConstantBackoff::new(Duration::ZERO)),
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        server.inject(Fault::Delay(Duration::from_millis(10)));
+        server.inject(Fault::Delay(Duration::from_millis(10)));
+        server.inject(Fault::DropConnection);
+        for i in 0..20 {
+            let mut map = Map::new();
+            map.insert("i".to_string(), i.into());
+            AsyncFluentClient::send(&client, "test", map).await.unwrap();
+        }
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        // records whose acks were lost with the connection may be received twice
+        let received = server.received();
+        for i in 0..20 {
+            assert!(received
+                .iter()
+                .any(|(_, _, map)| map.get("i") == Some(&i.into())));
+        }
+        let stats = client.stats();
+        assert_eq!(stats.acked, 20);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_acks() {
+        use std::io::Cursor;
+
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunks = Vec::new();
+            while chunks.len() < 2 {
+                stream.read_buf(&mut buf).await.unwrap();
+                let mut cursor = Cursor::new(&buf[..]);
+                while let Ok(frame) = rmpv::decode::read_value(&mut cursor) {
+                    chunks.push(frame[3]["chunk"].as_str().unwrap().to_string());
+                }
+                if chunks.len() < 2 {
+                    chunks.clear();
+                }
+            }
+            let mut acks = Vec::new();
+            for chunk in chunks.into_iter().rev() {
+                let ack = rmpv::Value::Map(vec![("ack".into(), chunk.into())]);
+                rmpv::encode::write_value(&mut acks, &ack).unwrap();
+            }
+            stream.write_all(&acks).await.unwrap();
+            stream
+        });
+
+        let config = Config {
+            max_in_flight: 2,
+            ..Default::default()
+        };
+        let client = Client::new_tcp(addr, &config).await.unwrap();
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+
+        let stats = client.stats();
+        assert_eq!((stats.sent, stats.acked, stats.retried), (2, 2, 0));
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_ack() {
+        let (server, client) = connect(Config::default()).await;
+
+        server.inject(Fault::WrongAck);
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), AsyncFluentClient::flush(&client))
+            .await
+            .expect("flush did not return after a wrong ack")
+            .unwrap();
+
+        assert_eq!(server.received().len(), 1);
+        let stats = client.stats();
+        assert_eq!((stats.sent, stats.acked), (2, 1));
+        assert_eq!((stats.retried, stats.reconnects), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_ack_timeout() {
+        let (failed, mut failures) = unbounded_channel();
+        let config = Config {
+            ack_timeout: Duration::from_millis(50),
+            retry_policy: Arc::new(ConstantBackoff::new(Duration::ZERO).with_max_retries(1)),
+            error_handler: Some(Arc::new(failed)),
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        server.inject(Fault::Delay(Duration::from_secs(1)));
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+        let stats = client.stats();
+        assert_eq!((stats.retried, stats.reconnects, stats.acked), (1, 1, 1));
+
+        server.inject(Fault::Delay(Duration::from_secs(1)));
+        server.inject(Fault::Delay(Duration::from_secs(1)));
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+        let (_, error) = failures.try_recv().expect("failed record was not handled");
+        match error {
+            Error::MaxRetriesExceeded { last, .. } => {
+                assert!(matches!(last.as_deref(), Some(Error::AckTimeout(_))));
+            }
+            e => unreachable!("unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let config = Config {
+            health_check_interval: Duration::from_millis(20),
+            tcp: TcpOptions {
+                keepalive: Some(TcpKeepaliveOptions::new(Duration::from_secs(30))),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        while server.connections() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        server.close_connections();
+        for _ in 0..100 {
+            if client.stats().reconnects > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.stats().reconnects, 1);
+        assert_eq!(server.connections(), 2);
+
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+        assert_eq!(server.received().len(), 1);
+        assert_eq!(client.stats().retried, 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let config = Config {
+            retry_policy: Arc::new(
+                ConstantBackoff::new(Duration::from_millis(10)).with_max_retries(2),
+            ),
+            ..Default::default()
+        };
+        let (server, client) = connect(config).await;
+
+        server.inject(Fault::DropConnection);
+        server.inject(Fault::DropConnection);
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+        assert_eq!(server.received().len(), 1);
+
+        server.inject(Fault::DropConnection);
+        server.inject(Fault::DropConnection);
+        server.inject(Fault::DropConnection);
+        AsyncFluentClient::send(&client, "test", Map::new())
+            .await
+            .unwrap();
+        AsyncFluentClient::flush(&client).await.unwrap();
+        assert_eq!(server.received().len(), 1);
+
+        let stats = client.stats();
+        assert_eq!(stats.retried, 4);
+        assert_eq!(stats.acked, 1);
+        assert_eq!(stats.failed, 1);
+    }
+}